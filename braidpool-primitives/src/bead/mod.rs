@@ -1,32 +1,93 @@
 #![allow(unused)]
 // Standard Imports
 use ::serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
 use std::net::SocketAddr;
 
 // Bitcoin primitives
 use bitcoin::Address;
 use bitcoin::absolute::Time;
 use bitcoin::ecdsa::Signature;
+use bitcoin::hashes::sha256;
 use bitcoin::p2p::Address as P2P_Address;
 use bitcoin::secp256k1::PublicKey;
 use bitcoin::transaction::TransactionExt;
-use bitcoin::{BlockHeader, Transaction};
+use bitcoin::{BlockHeader, Target, Transaction};
 // Custom Imports
 use crate::utils::BeadHash;
+
+// Encoding limits for committed metadata, so an oversized bead can't be used
+// to exhaust memory or bandwidth. A braid may be configured with a lower
+// parent limit, never a higher one.
+pub const MAX_PARENTS: usize = 16;
+// A block can't hold more transactions than fit its weight at the minimum
+// transaction size of 60 vbytes
+pub const MAX_TRANSACTIONS: usize = 4_000_000 / 240;
+// Longest valid address encoding (bech32)
+pub const MAX_PAYOUT_ADDRESS_LENGTH: usize = 90;
+
 #[derive(Clone, Debug, Serialize)]
 
 pub struct CommittedMetadata {
     // Committed Braidpool Metadata,
     pub transaction_cnt: u32,
     pub transactions: Vec<Transaction>,
-    pub parents: HashSet<BeadHash>,
+    // Kept sorted so the committed encoding is canonical
+    pub parents: BTreeSet<BeadHash>,
     pub payout_address: Address,
     //timestamp when the bead was created
     pub observed_time_at_node: Time,
     pub comm_pub_key: PublicKey,
-    pub miner_ip: SocketAddr,
+    pub miner_ip: MinerIp,
+}
+// How a bead commits to the address of the node its miner used. The raw
+// address leaks the miner's IP to every peer, so miners may commit a salted
+// hash of it, or nothing. Serialized untagged, so beads committing a raw
+// address keep their existing encoding.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MinerIp {
+    Raw(SocketAddr),
+    SaltedHash(sha256::Hash),
+    Empty,
+}
+
+// Which form of `MinerIp` a node commits for its own beads
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MinerIpPolicy {
+    Raw,
+    #[default]
+    SaltedHash,
+    Empty,
+}
+
+impl MinerIp {
+    pub fn commit(address: SocketAddr, policy: MinerIpPolicy, salt: &[u8]) -> Self {
+        match policy {
+            MinerIpPolicy::Raw => MinerIp::Raw(address),
+            MinerIpPolicy::SaltedHash => {
+                let mut preimage = salt.to_vec();
+                preimage.extend_from_slice(address.to_string().as_bytes());
+                MinerIp::SaltedHash(sha256::Hash::hash(&preimage))
+            }
+            MinerIpPolicy::Empty => MinerIp::Empty,
+        }
+    }
+
+    // Whether this commits to `address` under `salt`, for a miner proving
+    // which node it used
+    pub fn matches(&self, address: SocketAddr, salt: &[u8]) -> bool {
+        match self {
+            MinerIp::Raw(raw) => *raw == address,
+            MinerIp::SaltedHash(_) => {
+                *self == MinerIp::commit(address, MinerIpPolicy::SaltedHash, salt)
+            }
+            MinerIp::Empty => false,
+        }
+    }
 }
+
 #[derive(Clone, Debug, Serialize)]
 
 pub struct UnCommittedMetadata {
@@ -45,11 +106,54 @@ pub struct Bead {
     pub uncommitted_metadata: UnCommittedMetadata,
 }
 
+impl CommittedMetadata {
+    pub fn validate(&self) -> Result<(), MetadataError> {
+        if self.parents.len() > MAX_PARENTS {
+            return Err(MetadataError::TooManyParents);
+        }
+
+        if self.transactions.len() > MAX_TRANSACTIONS {
+            return Err(MetadataError::TooManyTransactions);
+        }
+
+        if self.transaction_cnt as usize != self.transactions.len() {
+            return Err(MetadataError::TransactionCountMismatch);
+        }
+
+        let mut txids = HashSet::with_capacity(self.transactions.len());
+        let mut spent = HashSet::new();
+        for transaction in &self.transactions {
+            if !txids.insert(transaction.compute_txid()) {
+                return Err(MetadataError::DuplicateTransaction);
+            }
+            if transaction.is_coinbase() {
+                continue;
+            }
+            for input in &transaction.input {
+                if !spent.insert(input.previous_output) {
+                    return Err(MetadataError::ConflictingTransactions);
+                }
+            }
+        }
+
+        if self.payout_address.to_string().len() > MAX_PAYOUT_ADDRESS_LENGTH {
+            return Err(MetadataError::PayoutAddressTooLong);
+        }
+
+        Ok(())
+    }
+}
+
 impl Bead {
     pub fn is_valid_bead(&self) -> bool {
         // Check whether the transactions are included in the block
-        true
+        self.committed_metadata.validate().is_ok()
+    }
+    // Whether the header also meets Bitcoin's target, making the bead a block
+    pub fn is_bitcoin_block(&self) -> bool {
+        Target::from_compact(self.block_header.bits).is_met_by(self.block_header.block_hash())
     }
+
     pub fn get_coinbase_transaction(&self) -> Transaction {
         // TODO: Implement this function.
         unimplemented!()
@@ -66,10 +170,39 @@ impl Bead {
     //TODO : To implement a reverse mapping since we will be including the
     //consensus determining attribute in the committed portion and those which
     //will be used afterward such as in retargeting algorithms such as the parentbead_timestamps they shall be
-    //included inside the uncommitted portion but the order must be same as that of the hashset of parents_bead_hashes present
+    //included inside the uncommitted portion but the order must be same as that of the sorted set of parents_bead_hashes present
     //inside the committed metadata
     pub fn reverse_mapping_parentbead_with_timestamp() {}
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetadataError {
+    TooManyParents,
+    TooManyTransactions,
+    TransactionCountMismatch,
+    DuplicateTransaction,
+    ConflictingTransactions,
+    PayoutAddressTooLong,
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataError::TooManyParents => write!(f, "Too many parents"),
+            MetadataError::TooManyTransactions => write!(f, "Too many transactions"),
+            MetadataError::TransactionCountMismatch => {
+                write!(f, "Transaction count does not match transactions")
+            }
+            MetadataError::DuplicateTransaction => write!(f, "Duplicate transaction"),
+            MetadataError::ConflictingTransactions => {
+                write!(f, "Transactions spend the same outpoint")
+            }
+            MetadataError::PayoutAddressTooLong => write!(f, "Payout address too long"),
+        }
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
 #[cfg(test)]
 mod tests;
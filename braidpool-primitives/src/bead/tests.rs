@@ -18,13 +18,15 @@ use secp256k1::Message;
 use secp256k1::{Secp256k1, SecretKey};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::json;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::str::FromStr;
 
 use super::Bead;
 use super::CommittedMetadata;
 use super::UnCommittedMetadata;
+use super::{MAX_PARENTS, MetadataError, MinerIp, MinerIpPolicy};
+use crate::utils::BeadHash;
 #[test]
 
 fn test_serialized_bead() {
@@ -39,12 +41,12 @@ fn test_serialized_bead() {
 
     let test_committed_metadata = CommittedMetadata {
         transaction_cnt: 0,
-        parents: HashSet::new(),
+        parents: BTreeSet::new(),
         transactions: vec![],
         payout_address: _address,
         comm_pub_key: public_key,
         observed_time_at_node: Time::from_consensus(1653195600).unwrap(),
-        miner_ip: socket,
+        miner_ip: MinerIp::Raw(socket),
     };
     let hex = "3046022100839c1fbc5304de944f697c9f4b1d01d1faeba32d751c0f7acb21ac8a0f436a72022100e89bd46bb3a5a62adc679f659b7ce876d83ee297c7a5587b2011c4fcc72eab45";
     let sig = Signature {
@@ -77,3 +79,66 @@ fn test_serialized_bead() {
         r#"{"block_header":{"version":2,"prev_blockhash":"0000000000000000000000000000000000000000000000000000000000000000","merkle_root":"0000000000000000000000000000000000000000000000000000000000000000","time":8328429,"bits":32,"nonce":1},"committed_metadata":{"transaction_cnt":0,"transactions":[],"parents":[],"payout_address":"32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf","observed_time_at_node":1653195600,"comm_pub_key":"02b98a7fb8cc007048625b6446ad49a1b3a722df8c1ca975b87160023e14d19097","miner_ip":"127.0.0.1:8080"},"uncommitted_metadata":{"extra_nonce":12,"broadcast_timestamp":1653195600,"signature":{"signature":"3046022100839c1fbc5304de944f697c9f4b1d01d1faeba32d751c0f7acb21ac8a0f436a72022100e89bd46bb3a5a62adc679f659b7ce876d83ee297c7a5587b2011c4fcc72eab45","sighash_type":"SIGHASH_ALL"},"parent_bead_timestamps":[]}}"#
     );
 }
+
+#[test]
+fn test_committed_metadata_validation() {
+    let address: Address = Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf")
+        .unwrap()
+        .require_network(Network::Bitcoin)
+        .unwrap();
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_byte_array(&[0xcd; 32]).expect("32 bytes, within curve order");
+    let mut metadata = CommittedMetadata {
+        transaction_cnt: 0,
+        parents: BTreeSet::new(),
+        transactions: vec![],
+        payout_address: address,
+        comm_pub_key: PublicKey::from_secret_key(&secp, &secret_key),
+        observed_time_at_node: Time::from_consensus(1653195600).unwrap(),
+        miner_ip: MinerIp::Raw(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            8080,
+        )),
+    };
+    assert_eq!(metadata.validate(), Ok(()));
+
+    metadata.transaction_cnt = 1;
+    assert_eq!(
+        metadata.validate(),
+        Err(MetadataError::TransactionCountMismatch)
+    );
+    metadata.transaction_cnt = 0;
+
+    for i in 0..=MAX_PARENTS {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&(i as u64).to_le_bytes());
+        metadata.parents.insert(BeadHash::from_byte_array(bytes));
+    }
+    assert_eq!(metadata.validate(), Err(MetadataError::TooManyParents));
+}
+
+#[test]
+fn test_miner_ip_commitments() {
+    let address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)), 8333);
+    let salt = b"braidpool";
+
+    let raw = MinerIp::commit(address, MinerIpPolicy::Raw, salt);
+    let hashed = MinerIp::commit(address, MinerIpPolicy::SaltedHash, salt);
+    let empty = MinerIp::commit(address, MinerIpPolicy::Empty, salt);
+
+    assert!(raw.matches(address, salt));
+    assert!(hashed.matches(address, salt));
+    assert!(!hashed.matches(address, b"other salt"));
+    assert!(!empty.matches(address, salt));
+
+    // Every form round trips, and raw addresses keep their old encoding
+    assert_eq!(
+        serde_json::to_string(&raw).unwrap(),
+        r#""203.0.113.7:8333""#
+    );
+    assert_eq!(serde_json::to_string(&empty).unwrap(), "null");
+    for miner_ip in [raw, hashed, empty] {
+        let json = serde_json::to_string(&miner_ip).unwrap();
+        assert_eq!(serde_json::from_str::<MinerIp>(&json).unwrap(), miner_ip);
+    }
+}
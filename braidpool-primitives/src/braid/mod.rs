@@ -1,12 +1,16 @@
 // Standard Imports
-use ::serde::Serialize;
+use ::serde::{Serialize, Serializer};
+use std::collections::hash_map::RandomState;
 use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
 // Bitcoin Imports
-use bitcoin::CompactTarget;
+use bitcoin::{BlockHeader, CompactTarget, OutPoint, Target, Txid};
 
 // Custom Imports
-use crate::bead::Bead;
+use crate::bead::{Bead, MAX_PARENTS};
 use crate::utils::BeadHash;
 
 // Type Definitions
@@ -19,10 +23,22 @@ pub enum AddBeadStatus {
     InvalidBead,
     BeadAdded,
     ParentsNotYetReceived,
+    OrphanQuotaExceeded,
 }
 
+// Upper bounds on the number of orphan beads held while waiting for their
+// parents, in total and from any one peer
+pub const MAX_ORPHANS: usize = 512;
+pub const MAX_ORPHANS_PER_PEER: usize = 64;
+// How long an orphan may wait for its parents before it is dropped
+pub const ORPHAN_EXPIRY: Duration = Duration::from_secs(10 * 60);
+
+// Where a bead came from, beads mined locally have no peer
+pub type BeadSource = Option<SocketAddr>;
+
 // Type Aliases
 type NumberOfBeadsUnorphaned = usize;
+type SpendingBeads = HashMap<Txid, HashSet<BeadHash>>;
 #[derive(Clone, Debug, Serialize)]
 
 pub struct Braid {
@@ -30,10 +46,146 @@ pub struct Braid {
     tips: HashSet<BeadHash>,
     cohorts: Vec<Cohort>,
 
-    orphan_beads: Vec<Bead>,
+    orphan_beads: OrphanPool,
+    max_parents: usize,
+    // Share target every bead's header must meet, if any
+    #[serde(skip)]
+    min_relay_target: Option<Target>,
 
     // Database related functions!
     loaded_beads_in_memory: HashMap<BeadHash, Bead>,
+
+    // Conflict tracking: for every outpoint spent by a committed transaction,
+    // the beads committing to each distinct spending transaction. Spends are
+    // kept for one regeneration, so this is bounded by the beads of the
+    // current and previous braid, older spends are left to bitcoind.
+    #[serde(skip)]
+    spent_outpoints: HashMap<OutPoint, SpendingBeads>,
+}
+
+// Orphan beads by hash, with where and when they arrived. Each peer may only
+// hold a quota of the pool, and when the pool is full a random orphan makes
+// way, so a peer sending children of parents that don't exist can't exhaust
+// memory or reliably push out other peers' orphans. Local beads have no
+// quota. Peers exceeding their quota, or whose orphans expire before they
+// resolve, collect penalties. Being evicted to make room is no fault of the
+// victim's peer, so it costs nothing.
+#[derive(Clone, Debug)]
+struct OrphanPool<T = Bead> {
+    orphans: HashMap<BeadHash, (T, BeadSource, Instant)>,
+    per_source: HashMap<BeadSource, usize>,
+    penalties: HashMap<SocketAddr, usize>,
+}
+
+impl<T> Default for OrphanPool<T> {
+    fn default() -> Self {
+        OrphanPool {
+            orphans: HashMap::new(),
+            per_source: HashMap::new(),
+            penalties: HashMap::new(),
+        }
+    }
+}
+
+impl<T> OrphanPool<T> {
+    fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    // Returns false if the peer `source` is already holding its quota of
+    // orphans
+    fn insert(&mut self, bead_hash: BeadHash, orphan: T, source: BeadSource) -> bool {
+        if self.orphans.contains_key(&bead_hash) {
+            return true;
+        }
+        if source.is_some()
+            && self.per_source.get(&source).copied().unwrap_or(0) >= MAX_ORPHANS_PER_PEER
+        {
+            self.penalize(source);
+            return false;
+        }
+        if self.orphans.len() >= MAX_ORPHANS {
+            self.evict_random();
+        }
+
+        self.orphans
+            .insert(bead_hash, (orphan, source, Instant::now()));
+        *self.per_source.entry(source).or_insert(0) += 1;
+        true
+    }
+
+    // Removes and returns every orphan for which `resolved` is true, with
+    // where it came from
+    fn take_resolved(&mut self, mut resolved: impl FnMut(&T) -> bool) -> Vec<(T, BeadSource)> {
+        let resolved: Vec<BeadHash> = self
+            .orphans
+            .iter()
+            .filter(|(_, (orphan, _, _))| resolved(orphan))
+            .map(|(bead_hash, _)| *bead_hash)
+            .collect();
+        resolved
+            .iter()
+            .filter_map(|bead_hash| self.remove(bead_hash))
+            .collect()
+    }
+
+    // Drops and penalizes every orphan older than `ORPHAN_EXPIRY` at `now`,
+    // returning how many
+    fn expire(&mut self, now: Instant) -> usize {
+        let expired: Vec<BeadHash> = self
+            .orphans
+            .iter()
+            .filter(|(_, (_, _, received))| {
+                now.saturating_duration_since(*received) > ORPHAN_EXPIRY
+            })
+            .map(|(bead_hash, _)| *bead_hash)
+            .collect();
+        for bead_hash in &expired {
+            if let Some((_, source)) = self.remove(bead_hash) {
+                self.penalize(source);
+            }
+        }
+
+        expired.len()
+    }
+
+    fn remove(&mut self, bead_hash: &BeadHash) -> Option<(T, BeadSource)> {
+        let (orphan, source, _) = self.orphans.remove(bead_hash)?;
+        if let Some(count) = self.per_source.get_mut(&source) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_source.remove(&source);
+            }
+        }
+
+        Some((orphan, source))
+    }
+
+    fn evict_random(&mut self) {
+        if self.orphans.is_empty() {
+            return;
+        }
+        // A freshly keyed RandomState is enough randomness to keep the
+        // victim unpredictable to peers
+        let index = RandomState::new().hash_one(self.orphans.len()) as usize % self.orphans.len();
+        let Some(bead_hash) = self.orphans.keys().nth(index).copied() else {
+            return;
+        };
+        self.remove(&bead_hash);
+    }
+
+    fn penalize(&mut self, source: BeadSource) {
+        if let Some(peer) = source {
+            *self.penalties.entry(peer).or_insert(0) += 1;
+        }
+    }
+}
+
+// Serialized as the list of orphan beads, like the Vec it replaced
+impl<T: Serialize> Serialize for OrphanPool<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.orphans.values().map(|(orphan, _, _)| orphan))
+    }
 }
 
 impl Braid {
@@ -43,49 +195,181 @@ impl Braid {
             beads: genesis_beads.clone(),
             tips: genesis_beads.clone(),
             cohorts: vec![Cohort(genesis_beads)],
-            orphan_beads: Vec::new(),
+            orphan_beads: OrphanPool::default(),
+            max_parents: MAX_PARENTS,
+            min_relay_target: None,
             loaded_beads_in_memory: HashMap::new(),
+            spent_outpoints: HashMap::new(),
         }
     }
 
     pub fn generate_from_previous_dag(previous_dag_braid: Braid) -> Self {
         let cohorts = previous_dag_braid.generate_tip_cohorts();
+        let mut spent_outpoints = previous_dag_braid.spent_outpoints;
+        let previous_beads = &previous_dag_braid.loaded_beads_in_memory;
+        spent_outpoints.retain(|_, spenders| {
+            spenders.retain(|_, bead_hashes| {
+                bead_hashes.retain(|bead_hash| previous_beads.contains_key(bead_hash));
+                !bead_hashes.is_empty()
+            });
+            !spenders.is_empty()
+        });
         Braid {
             beads: previous_dag_braid.tips.clone(),
             tips: previous_dag_braid.tips,
             cohorts,
-            orphan_beads: Vec::new(),
+            orphan_beads: OrphanPool::default(),
+            max_parents: previous_dag_braid.max_parents,
+            min_relay_target: previous_dag_braid.min_relay_target,
             loaded_beads_in_memory: HashMap::new(),
+            spent_outpoints,
+        }
+    }
+
+    /// Lowers the number of parents a bead may commit to, which can't be
+    /// raised above `MAX_PARENTS`.
+    pub fn with_max_parents(mut self, max_parents: usize) -> Self {
+        self.max_parents = max_parents.min(MAX_PARENTS);
+        self
+    }
+
+    /// Refuses beads whose header hash doesn't meet the share target `target`.
+    pub fn with_min_relay_target(mut self, target: Target) -> Self {
+        self.min_relay_target = Some(target);
+        self
+    }
+
+    /// Whether `header` meets the minimum relay target. A header's `bits` is
+    /// Bitcoin's target, which beads normally don't meet, so it only decides
+    /// whether a bead is also a block (`Bead::is_bitcoin_block`). This only
+    /// hashes the header, so gossip can discard cheap garbage before decoding
+    /// the bead's metadata.
+    pub fn check_header(&self, header: &BlockHeader) -> bool {
+        match self.min_relay_target {
+            Some(min_relay_target) => {
+                min_relay_target.is_met_by(*BeadHash::from_header(header).as_block_hash())
+            }
+            None => true,
         }
     }
 
     pub fn add_bead(&mut self, bead: Bead) -> AddBeadStatus {
-        if bead.is_valid_bead() == false {
+        self.add_bead_from(bead, None)
+    }
+
+    /// Adds a bead received from `source`, which is charged for it while it
+    /// waits in the orphan pool. Orphans the bead resolves are added with
+    /// it, and orphans which have waited too long are dropped.
+    pub fn add_bead_from(&mut self, bead: Bead, source: BeadSource) -> AddBeadStatus {
+        self.orphan_beads.expire(Instant::now());
+        let status = self.insert_bead(bead, source);
+        if let AddBeadStatus::BeadAdded = status {
+            self.update_orphan_bead_set();
+        }
+
+        status
+    }
+
+    /// Returns every bead in the braid which commits to a transaction spending
+    /// one of the outpoints spent by `bead`, but with a different txid.
+    pub fn conflicting_beads(&self, bead: &Bead) -> HashSet<BeadHash> {
+        let mut conflicts = HashSet::new();
+        for transaction in &bead.committed_metadata.transactions {
+            if transaction.is_coinbase() {
+                continue;
+            }
+
+            let txid = transaction.compute_txid();
+            for input in &transaction.input {
+                let Some(spenders) = self.spent_outpoints.get(&input.previous_output) else {
+                    continue;
+                };
+                for (spending_txid, bead_hashes) in spenders {
+                    if *spending_txid != txid {
+                        conflicts.extend(bead_hashes.iter().cloned());
+                    }
+                }
+            }
+        }
+
+        conflicts
+    }
+
+    /// Number of beads waiting for their parents.
+    pub fn orphan_count(&self) -> usize {
+        self.orphan_beads.len()
+    }
+
+    /// Peers penalized since the last call, with how many of their orphans
+    /// expired unresolved or were refused over quota, for the network layer
+    /// to score.
+    pub fn take_orphan_penalties(&mut self) -> HashMap<SocketAddr, usize> {
+        std::mem::take(&mut self.orphan_beads.penalties)
+    }
+
+    /// Whether any two beads in the braid commit to conflicting transactions.
+    pub fn contains_conflicts(&self) -> bool {
+        self.spent_outpoints
+            .values()
+            .any(|spenders| spenders.len() > 1)
+    }
+}
+
+impl Braid {
+    // All private functions go here!
+
+    // Validates `bead` and adds it to the braid, or to the orphan pool if
+    // any of its parents is missing
+    fn insert_bead(&mut self, bead: Bead, source: BeadSource) -> AddBeadStatus {
+        // Proof of work is the cheapest check to fail, so it comes first
+        if !self.check_header(&bead.block_header) {
+            return AddBeadStatus::InvalidBead;
+        }
+
+        // The header doesn't commit to the metadata yet, so a bead can't be
+        // told apart from one with the same header and junk metadata, and
+        // every copy is validated
+        if !bead.is_valid_bead() || bead.committed_metadata.parents.len() > self.max_parents {
             return AddBeadStatus::InvalidBead;
         }
 
-        // if self.contains_bead(bead.bead_hash) {
-        //     return AddBeadStatus::DagAlreadyContainsBead;
-        // }
+        let bead_hash = BeadHash::from_header(&bead.block_header);
+        if self.contains_bead(bead_hash) {
+            return AddBeadStatus::DagAlreadyContainsBead;
+        }
 
         if self.is_bead_orphaned(&bead) {
-            self.orphan_beads.push(bead);
+            if !self.orphan_beads.insert(bead_hash, bead, source) {
+                return AddBeadStatus::OrphanQuotaExceeded;
+            }
             return AddBeadStatus::ParentsNotYetReceived;
         }
 
-        // self.beads.insert(bead.bead_hash);
+        // Children must not double-spend against their ancestors. Beads from
+        // before the braid was regenerated are no longer loaded and are
+        // settled, so conflicting with them counts the same. Conflicts with
+        // any other bead are kept and resolved by descendant work.
+        let conflicts = self.conflicting_beads(&bead);
+        if !conflicts.is_empty() {
+            let ancestors = self.ancestors(&bead);
+            if conflicts.iter().any(|conflict| {
+                ancestors.contains(conflict) || !self.loaded_beads_in_memory.contains_key(conflict)
+            }) {
+                return AddBeadStatus::InvalidBead;
+            }
+        }
+
+        self.index_spent_outpoints(&bead);
         self.remove_parent_beads_from_tips(&bead);
-        // self.tips.insert(bead.bead_hash);
+        self.beads.insert(bead_hash);
+        self.tips.insert(bead_hash);
+        self.loaded_beads_in_memory.insert(bead_hash, bead);
 
         self.cohorts = self.calculate_cohorts();
-        self.update_orphan_bead_set();
 
         AddBeadStatus::BeadAdded
     }
-}
 
-impl Braid {
-    // All private functions go here!
     fn calculate_cohorts(&self) -> Vec<Cohort> {
         // TODO: Implement the cohorts calculating function!
         vec![Cohort(HashSet::new())]
@@ -149,6 +433,23 @@ impl Braid {
         }
     }
 
+    // Every bead reachable through the parents of `bead`, as far back as
+    // beads are loaded
+    fn ancestors(&self, bead: &Bead) -> HashSet<BeadHash> {
+        let mut ancestors = HashSet::new();
+        let mut pending: Vec<BeadHash> = bead.committed_metadata.parents.iter().copied().collect();
+        while let Some(bead_hash) = pending.pop() {
+            if !ancestors.insert(bead_hash) {
+                continue;
+            }
+            if let Some(ancestor) = self.loaded_beads_in_memory.get(&bead_hash) {
+                pending.extend(ancestor.committed_metadata.parents.iter().copied());
+            }
+        }
+
+        ancestors
+    }
+
     #[inline]
     fn remove_parent_beads_from_tips(&mut self, bead: &Bead) {
         for parent_hash in &bead.committed_metadata.parents {
@@ -156,6 +457,25 @@ impl Braid {
         }
     }
 
+    fn index_spent_outpoints(&mut self, bead: &Bead) {
+        let bead_hash = BeadHash::from_header(&bead.block_header);
+        for transaction in &bead.committed_metadata.transactions {
+            if transaction.is_coinbase() {
+                continue;
+            }
+
+            let txid = transaction.compute_txid();
+            for input in &transaction.input {
+                self.spent_outpoints
+                    .entry(input.previous_output)
+                    .or_default()
+                    .entry(txid)
+                    .or_default()
+                    .insert(bead_hash);
+            }
+        }
+    }
+
     #[inline]
     fn is_bead_orphaned(&self, bead: &Bead) -> bool {
         for parent in &bead.committed_metadata.parents {
@@ -167,16 +487,29 @@ impl Braid {
         false
     }
 
+    // Adds every orphan whose parents have all arrived, along with any of
+    // their own orphans this resolves in turn
     fn update_orphan_bead_set(&mut self) -> NumberOfBeadsUnorphaned {
-        let old_orphan_set_length = self.orphan_beads.len();
-        let old_orphan_set = std::mem::replace(&mut self.orphan_beads, Vec::new());
-        for orphan_bead in old_orphan_set {
-            if self.is_bead_orphaned(&orphan_bead) {
-                self.orphan_beads.push(orphan_bead)
+        let mut unorphaned = 0;
+        loop {
+            let beads = &self.beads;
+            let resolved = self.orphan_beads.take_resolved(|orphan_bead| {
+                orphan_bead
+                    .committed_metadata
+                    .parents
+                    .iter()
+                    .all(|parent| beads.contains(parent))
+            });
+            if resolved.is_empty() {
+                return unorphaned;
             }
-        }
 
-        return old_orphan_set_length - self.orphan_beads.len();
+            for (bead, source) in resolved {
+                if let AddBeadStatus::BeadAdded = self.insert_bead(bead, source) {
+                    unorphaned += 1;
+                }
+            }
+        }
     }
 
     fn calculate_valid_difficulty_for_bead(&self, bead: &Bead) -> CompactTarget {
@@ -1 +1,354 @@
+use super::{AddBeadStatus, Braid, MAX_ORPHANS, MAX_ORPHANS_PER_PEER, ORPHAN_EXPIRY, OrphanPool};
+use crate::bead::{
+    Bead, CommittedMetadata, MAX_PARENTS, MetadataError, MinerIp, UnCommittedMetadata,
+};
+use crate::utils::BeadHash;
+use bitcoin::absolute::{LockTime, Time};
+use bitcoin::ecdsa::Signature;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{
+    Address, BlockHash, BlockHeader, BlockTime, BlockVersion, CompactTarget, EcdsaSighashType,
+    Network, OutPoint, Target, Transaction, TxIn, TxMerkleNode, Txid, transaction,
+};
+use secp256k1::{Secp256k1, SecretKey};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
+fn bead_hash(i: usize) -> BeadHash {
+    let mut bytes = [0u8; 32];
+    bytes[..8].copy_from_slice(&(i as u64).to_le_bytes());
+    BeadHash::from_byte_array(bytes)
+}
+
+fn peer(port: u16) -> SocketAddr {
+    SocketAddr::from(([192, 0, 2, 1], port))
+}
+
+#[test]
+fn test_orphan_pool_enforces_peer_quota() {
+    let mut pool = OrphanPool::<()>::default();
+    for i in 0..MAX_ORPHANS_PER_PEER {
+        assert!(pool.insert(bead_hash(i), (), Some(peer(1))));
+    }
+    // Inserting the same orphan again costs nothing
+    assert!(pool.insert(bead_hash(0), (), Some(peer(1))));
+    assert!(!pool.insert(bead_hash(MAX_ORPHANS_PER_PEER), (), Some(peer(1))));
+    assert!(pool.insert(bead_hash(MAX_ORPHANS_PER_PEER), (), Some(peer(2))));
+    assert_eq!(pool.penalties.get(&peer(1)), Some(&1));
+
+    // Resolved orphans free up the quota without a penalty
+    assert_eq!(pool.take_resolved(|_| true).len(), MAX_ORPHANS_PER_PEER + 1);
+    assert!(pool.insert(bead_hash(0), (), Some(peer(1))));
+    assert_eq!(pool.penalties.get(&peer(2)), None);
+}
+
+#[test]
+fn test_orphan_pool_evicts_when_full() {
+    let mut pool = OrphanPool::<()>::default();
+    let peers = MAX_ORPHANS.div_ceil(MAX_ORPHANS_PER_PEER) + 1;
+    for i in 0..peers * MAX_ORPHANS_PER_PEER {
+        assert!(pool.insert(bead_hash(i), (), Some(peer(i as u16 % peers as u16))));
+        assert!(pool.len() <= MAX_ORPHANS);
+    }
+    assert_eq!(pool.len(), MAX_ORPHANS);
+
+    // Being evicted to make room isn't the victim's fault
+    assert!(pool.penalties.is_empty());
+    assert_eq!(pool.per_source.values().sum::<usize>(), MAX_ORPHANS);
+}
+
+#[test]
+fn test_orphan_pool_exempts_local_beads_from_quota() {
+    let mut pool = OrphanPool::<()>::default();
+    for i in 0..=MAX_ORPHANS_PER_PEER {
+        assert!(pool.insert(bead_hash(i), (), None));
+    }
+    assert_eq!(pool.len(), MAX_ORPHANS_PER_PEER + 1);
+    assert!(pool.penalties.is_empty());
+}
+
+#[test]
+fn test_orphan_pool_penalizes_expired_orphans() {
+    let mut pool = OrphanPool::<()>::default();
+    assert!(pool.insert(bead_hash(0), (), Some(peer(1))));
+    assert!(pool.insert(bead_hash(1), (), Some(peer(1))));
+    assert!(pool.insert(bead_hash(2), (), None));
+
+    assert_eq!(pool.expire(Instant::now()), 0);
+    assert_eq!(
+        pool.expire(Instant::now() + ORPHAN_EXPIRY + Duration::from_secs(1)),
+        3
+    );
+    assert_eq!(pool.len(), 0);
+    assert_eq!(pool.penalties, HashMap::from([(peer(1), 2)]));
+}
+
+fn header(bits: u32, nonce: u32) -> BlockHeader {
+    BlockHeader {
+        version: BlockVersion::TWO,
+        prev_blockhash: BlockHash::from_byte_array([0u8; 32]),
+        merkle_root: TxMerkleNode::from_byte_array([0u8; 32]),
+        time: BlockTime::from_u32(1653195600),
+        bits: CompactTarget::from_consensus(bits),
+        nonce,
+    }
+}
+
+fn target(bits: u32) -> Target {
+    Target::from_compact(CompactTarget::from_consensus(bits))
+}
+
+#[test]
+fn test_resolved_orphans_join_the_braid() {
+    let (mut braid, genesis) = genesis();
+    let parent = bead(1, &[genesis], vec![]);
+    let child = bead(2, &[hash(&parent)], vec![]);
+    let grandchild = bead(3, &[hash(&child)], vec![]);
+
+    for orphan in [&grandchild, &child] {
+        assert!(matches!(
+            braid.add_bead_from(orphan.clone(), Some(peer(1))),
+            AddBeadStatus::ParentsNotYetReceived
+        ));
+    }
+    assert_eq!(braid.orphan_count(), 2);
+
+    assert!(matches!(
+        braid.add_bead_from(parent.clone(), Some(peer(2))),
+        AddBeadStatus::BeadAdded
+    ));
+    assert_eq!(braid.orphan_count(), 0);
+    assert_eq!(braid.orphan_beads.per_source.get(&Some(peer(1))), None);
+    for added in [&parent, &child, &grandchild] {
+        assert!(braid.beads.contains(&hash(added)));
+    }
+    assert_eq!(braid.tips, HashSet::from([hash(&grandchild)]));
+}
+
+#[test]
+fn test_check_header_against_relay_target() {
+    // Without a relay target every header is accepted
+    assert!(Braid::new(HashSet::new()).check_header(&header(0x1d00ffff, 0)));
+
+    // A share target of the easiest regtest target, which half of all hashes
+    // meet. Headers carry a mainnet-like Bitcoin target they don't meet.
+    let braid = Braid::new(HashSet::new()).with_min_relay_target(target(0x207fffff));
+    let share = (0..64)
+        .map(|nonce| header(0x1d00ffff, nonce))
+        .find(|header| braid.check_header(header))
+        .expect("a nonce meeting the relay target");
+    assert!(!target(0x1d00ffff).is_met_by(share.block_hash()));
+    let junk = (0..64)
+        .map(|nonce| header(0x1d00ffff, nonce))
+        .find(|header| !braid.check_header(header))
+        .expect("a nonce missing the relay target");
+
+    // A harder relay target refuses what the easier one accepted
+    let braid = braid.with_min_relay_target(target(0x1d00ffff));
+    assert!(!braid.check_header(&share));
+    assert!(!braid.check_header(&junk));
+}
+
+// A transaction spending `outpoint`. Different `lock_time`s give different
+// txids, so two spends of the same outpoint conflict.
+fn spend(outpoint: OutPoint, lock_time: u32) -> Transaction {
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::from_consensus(lock_time),
+        input: vec![TxIn {
+            previous_output: outpoint,
+            ..TxIn::EMPTY_COINBASE
+        }],
+        output: vec![],
+    }
+}
+
+fn outpoint(i: u8) -> OutPoint {
+    OutPoint {
+        txid: Txid::from_byte_array([i; 32]),
+        vout: 0,
+    }
+}
+
+// A valid bead with the given parents and transactions, made unique by `nonce`
+fn bead(nonce: u32, parents: &[BeadHash], transactions: Vec<Transaction>) -> Bead {
+    let secp = Secp256k1::new();
+    let secret_key = SecretKey::from_byte_array(&[0xcd; 32]).expect("32 bytes, within curve order");
+    let signature = "3046022100839c1fbc5304de944f697c9f4b1d01d1faeba32d751c0f7acb21ac8a0f436a72022100e89bd46bb3a5a62adc679f659b7ce876d83ee297c7a5587b2011c4fcc72eab45";
+    Bead {
+        block_header: header(0x1d00ffff, nonce),
+        committed_metadata: CommittedMetadata {
+            transaction_cnt: transactions.len() as u32,
+            transactions,
+            parents: parents.iter().copied().collect::<BTreeSet<_>>(),
+            payout_address: Address::from_str("32iVBEu4dxkUQk9dJbZUiBiQdmypcEyJRf")
+                .unwrap()
+                .require_network(Network::Bitcoin)
+                .unwrap(),
+            observed_time_at_node: Time::from_consensus(1653195600).unwrap(),
+            comm_pub_key: PublicKey::from_secret_key(&secp, &secret_key),
+            miner_ip: MinerIp::Empty,
+        },
+        uncommitted_metadata: UnCommittedMetadata {
+            extra_nonce: 0,
+            broadcast_timestamp: Time::from_consensus(1653195600).unwrap(),
+            signature: Signature {
+                signature: secp256k1::ecdsa::Signature::from_str(signature).unwrap(),
+                sighash_type: EcdsaSighashType::All,
+            },
+            parent_bead_timestamps: HashSet::new(),
+        },
+    }
+}
+
+fn hash(bead: &Bead) -> BeadHash {
+    BeadHash::from_header(&bead.block_header)
+}
+
+fn genesis() -> (Braid, BeadHash) {
+    let genesis = bead_hash(1_000_000);
+    (Braid::new(HashSet::from([genesis])), genesis)
+}
+
+#[test]
+fn test_conflicting_beads() {
+    let (mut braid, genesis) = genesis();
+    let a = bead(1, &[genesis], vec![spend(outpoint(1), 1)]);
+    let b = bead(2, &[genesis], vec![spend(outpoint(1), 2)]);
+    let c = bead(3, &[genesis], vec![spend(outpoint(1), 1)]);
+
+    assert!(matches!(
+        braid.add_bead(a.clone()),
+        AddBeadStatus::BeadAdded
+    ));
+    assert!(matches!(
+        braid.add_bead(a.clone()),
+        AddBeadStatus::DagAlreadyContainsBead
+    ));
+    assert!(braid.conflicting_beads(&c).is_empty());
+    assert!(matches!(
+        braid.add_bead(c.clone()),
+        AddBeadStatus::BeadAdded
+    ));
+    assert!(!braid.contains_conflicts());
+
+    // Siblings may conflict, descendant work decides between them
+    assert_eq!(
+        braid.conflicting_beads(&b),
+        HashSet::from([hash(&a), hash(&c)])
+    );
+    assert!(matches!(braid.add_bead(b), AddBeadStatus::BeadAdded));
+    assert!(braid.contains_conflicts());
+}
+
+#[test]
+fn test_children_must_not_conflict_with_ancestors() {
+    let (mut braid, genesis) = genesis();
+    let a = bead(1, &[genesis], vec![spend(outpoint(1), 1)]);
+    let b = bead(2, &[hash(&a)], vec![]);
+    assert!(matches!(
+        braid.add_bead(a.clone()),
+        AddBeadStatus::BeadAdded
+    ));
+    assert!(matches!(
+        braid.add_bead(b.clone()),
+        AddBeadStatus::BeadAdded
+    ));
+
+    let child = bead(3, &[hash(&a)], vec![spend(outpoint(1), 2)]);
+    assert!(matches!(braid.add_bead(child), AddBeadStatus::InvalidBead));
+    let grandchild = bead(4, &[hash(&b)], vec![spend(outpoint(1), 3)]);
+    assert!(matches!(
+        braid.add_bead(grandchild),
+        AddBeadStatus::InvalidBead
+    ));
+    let unrelated = bead(5, &[hash(&b)], vec![spend(outpoint(2), 1)]);
+    assert!(matches!(
+        braid.add_bead(unrelated),
+        AddBeadStatus::BeadAdded
+    ));
+
+    // Regenerating the braid keeps what was spent before it
+    let mut braid = Braid::generate_from_previous_dag(braid);
+    let tips: Vec<BeadHash> = braid.tips.iter().copied().collect();
+    let late = bead(6, &tips, vec![spend(outpoint(1), 4)]);
+    assert!(matches!(braid.add_bead(late), AddBeadStatus::InvalidBead));
+
+    // But only for one regeneration
+    let braid = Braid::generate_from_previous_dag(braid);
+    assert!(braid.spent_outpoints.is_empty());
+}
+
+#[test]
+fn test_beads_must_not_conflict_with_themselves() {
+    let (mut braid, genesis) = genesis();
+    let conflicted = bead(
+        1,
+        &[genesis],
+        vec![spend(outpoint(1), 1), spend(outpoint(1), 2)],
+    );
+    assert_eq!(
+        conflicted.committed_metadata.validate(),
+        Err(MetadataError::ConflictingTransactions)
+    );
+    assert!(matches!(
+        braid.add_bead(conflicted),
+        AddBeadStatus::InvalidBead
+    ));
+    assert!(!braid.contains_conflicts());
+}
+
+#[test]
+fn test_beads_over_max_parents_are_rejected() {
+    let (braid, genesis) = genesis();
+    let mut braid = braid.with_max_parents(2);
+    let parents: Vec<Bead> = (1..=3)
+        .map(|nonce| bead(nonce, &[genesis], vec![]))
+        .collect();
+    for parent in &parents {
+        assert!(matches!(
+            braid.add_bead(parent.clone()),
+            AddBeadStatus::BeadAdded
+        ));
+    }
+    let hashes: Vec<BeadHash> = parents.iter().map(hash).collect();
+
+    assert!(matches!(
+        braid.add_bead(bead(4, &hashes, vec![])),
+        AddBeadStatus::InvalidBead
+    ));
+    assert!(matches!(
+        braid.add_bead(bead(5, &hashes[..2], vec![])),
+        AddBeadStatus::BeadAdded
+    ));
+
+    // The limit can only be lowered
+    let braid = Braid::new(HashSet::from([genesis])).with_max_parents(MAX_PARENTS + 1);
+    assert_eq!(braid.max_parents, MAX_PARENTS);
+}
+
+#[test]
+fn test_metadata_is_validated_for_every_copy() {
+    let (mut braid, genesis) = genesis();
+    let honest = bead(1, &[genesis], vec![]);
+    let mut junk = honest.clone();
+    junk.committed_metadata.transaction_cnt = 1;
+    assert_eq!(hash(&junk), hash(&honest));
+
+    // Junk metadata under an honest header doesn't get the header refused
+    assert!(matches!(
+        braid.add_bead(junk.clone()),
+        AddBeadStatus::InvalidBead
+    ));
+    assert!(matches!(
+        braid.add_bead(honest.clone()),
+        AddBeadStatus::BeadAdded
+    ));
+
+    // Nor does having accepted the honest bead let junk metadata through
+    let mut braid = Braid::new(HashSet::from([genesis]));
+    assert!(matches!(braid.add_bead(honest), AddBeadStatus::BeadAdded));
+    assert!(matches!(braid.add_bead(junk), AddBeadStatus::InvalidBead));
+}
@@ -1,8 +1,74 @@
-use ::bitcoin::BlockHash;
+// Standard Imports
+use ::serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// Bitcoin Imports
+use ::bitcoin::{BlockHash, BlockHeader};
 
 pub mod bitcoin;
 
 // Type Definitions
-pub type BeadHash = BlockHash;
 pub type Byte = u8;
 pub type Bytes = Vec<Byte>;
+
+// Hash of a bead's block header. Kept distinct from `BlockHash` so bead hashes
+// can't be mixed up with the hashes of Bitcoin blocks. Orders by the internal
+// byte order, which is the canonical order for committing to sets of beads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BeadHash(BlockHash);
+
+impl BeadHash {
+    pub fn from_header(header: &BlockHeader) -> Self {
+        BeadHash(header.block_hash())
+    }
+
+    pub fn from_byte_array(bytes: [u8; 32]) -> Self {
+        BeadHash(BlockHash::from_byte_array(bytes))
+    }
+
+    pub fn to_byte_array(self) -> [u8; 32] {
+        self.0.to_byte_array()
+    }
+
+    pub fn as_block_hash(&self) -> &BlockHash {
+        &self.0
+    }
+
+    // The first 8 hex characters, for logs and debugging output
+    pub fn short(&self) -> String {
+        let mut hex = self.to_string();
+        hex.truncate(8);
+        hex
+    }
+}
+
+impl From<BlockHash> for BeadHash {
+    fn from(hash: BlockHash) -> Self {
+        BeadHash(hash)
+    }
+}
+
+impl From<BeadHash> for BlockHash {
+    fn from(hash: BeadHash) -> Self {
+        hash.0
+    }
+}
+
+impl fmt::Display for BeadHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for BeadHash {
+    type Err = <BlockHash as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BlockHash::from_str(s).map(BeadHash)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,38 @@
+use ::bitcoin::BlockHash;
+use std::str::FromStr;
+
+use super::BeadHash;
+
+const HASH_HEX: &str = "00000000000000000001c0f0d2b0c64a3ebb1a5a8ac1aa1fb8d4a1f7a3e6e2d1";
+
+#[test]
+fn test_bead_hash_display_and_parse() {
+    let bead_hash = BeadHash::from_str(HASH_HEX).unwrap();
+    assert_eq!(bead_hash.to_string(), HASH_HEX);
+    assert_eq!(bead_hash.short(), "00000000");
+    assert_eq!(
+        BlockHash::from(bead_hash),
+        BlockHash::from_str(HASH_HEX).unwrap()
+    );
+}
+
+#[test]
+fn test_bead_hash_serde_hex() {
+    let bead_hash = BeadHash::from_str(HASH_HEX).unwrap();
+    let json = serde_json::to_string(&bead_hash).unwrap();
+    assert_eq!(json, format!("\"{}\"", HASH_HEX));
+    assert_eq!(serde_json::from_str::<BeadHash>(&json).unwrap(), bead_hash);
+}
+
+#[test]
+fn test_bead_hash_canonical_order() {
+    let mut low = [0u8; 32];
+    low[0] = 1;
+    let mut high = [0u8; 32];
+    high[0] = 2;
+
+    let low = BeadHash::from_byte_array(low);
+    let high = BeadHash::from_byte_array(high);
+    assert!(low < high);
+    assert_eq!(BeadHash::from_byte_array(low.to_byte_array()), low);
+}
@@ -0,0 +1,93 @@
+//! Summary statistics for a braid, for plotting and comparing braids
+//!
+//! Statistics are computed per cohort: its size, the work it contains and the cumulative work up
+//! to and including it. When bead timestamps are known (e.g. from a `Fixture`), the cohorts are
+//! also placed in time and the bead inter-arrival times are collected into a histogram.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::braid::io_json::Dag;
+use crate::braid::{BeadHash, Work};
+
+/// Statistics for one cohort, in order from genesis
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CohortStats {
+    pub index: usize,
+    pub beads: usize,
+    /// Work as a decimal string, since it doesn't fit in a JSON number
+    pub work: String,
+    pub cumulative_work: String,
+    /// Latest timestamp of a bead in the cohort
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BraidSummary {
+    pub description: Option<String>,
+    pub beads: usize,
+    pub cohorts: Vec<CohortStats>,
+    /// Number of cohorts of each size
+    pub cohort_sizes: BTreeMap<usize, usize>,
+    /// Number of times each gap in seconds occurs between consecutive beads in time order
+    pub interval_histogram: BTreeMap<u64, usize>,
+}
+
+impl BraidSummary {
+    /// One row per cohort
+    pub fn cohorts_csv(&self) -> String {
+        let mut csv = String::from("cohort,beads,work,cumulative_work,timestamp\n");
+        for cohort in &self.cohorts {
+            let timestamp = cohort.timestamp.map(|t| t.to_string()).unwrap_or_default();
+            csv.push_str(&format!(
+                "{},{},{},{},{}\n",
+                cohort.index, cohort.beads, cohort.work, cohort.cumulative_work, timestamp
+            ));
+        }
+        csv
+    }
+}
+
+/// Summarize `dag`. `timestamps` may be empty, or cover only some beads.
+pub fn summarize(dag: &Dag, timestamps: &HashMap<BeadHash, u64>) -> BraidSummary {
+    let mut cumulative_work = Work::from(0u32);
+    let mut cohorts = Vec::with_capacity(dag.cohorts.len());
+    let mut cohort_sizes = BTreeMap::new();
+
+    for (index, cohort) in dag.cohorts.iter().enumerate() {
+        let work: Work = cohort
+            .iter()
+            .filter_map(|bead| dag.bead_work.get(bead))
+            .sum();
+        cumulative_work += &work;
+        *cohort_sizes.entry(cohort.len()).or_insert(0) += 1;
+
+        cohorts.push(CohortStats {
+            index,
+            beads: cohort.len(),
+            work: work.to_string(),
+            cumulative_work: cumulative_work.to_string(),
+            timestamp: cohort
+                .iter()
+                .filter_map(|b| timestamps.get(b))
+                .max()
+                .copied(),
+        });
+    }
+
+    let mut times: Vec<u64> = timestamps.values().copied().collect();
+    times.sort_unstable();
+    let mut interval_histogram = BTreeMap::new();
+    for pair in times.windows(2) {
+        *interval_histogram.entry(pair[1] - pair[0]).or_insert(0) += 1;
+    }
+
+    BraidSummary {
+        description: dag.description.clone(),
+        beads: dag.parents.len(),
+        cohorts,
+        cohort_sizes,
+        interval_histogram,
+    }
+}
@@ -0,0 +1,245 @@
+//! Banned peer addresses, persisted in the data directory
+//!
+//! Bans are IP subnets in CIDR notation (`203.0.113.0/24`, `2001:db8::/32`), with a bare address
+//! banning just that address. They are stored as a JSON list in `<datadir>/banlist.json`, which
+//! `braidpool-cli ban`, `unban` and `listbanned` edit. A running node notices when the file
+//! changes, so bans apply without a restart.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const BAN_FILE_NAME: &str = "banlist.json";
+
+/// A range of IP addresses sharing a prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Subnet {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    /// The subnet of `addr` with a `prefix` bit long network part, or `None` if the prefix is
+    /// longer than the address
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Subnet> {
+        let network = match addr.to_canonical() {
+            IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask::<u32>(prefix, 32)?)),
+            IpAddr::V6(v6) => {
+                IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask::<u128>(prefix, 128)?))
+            }
+        };
+        Some(Subnet { network, prefix })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        Subnet::new(addr, self.prefix) == Some(*self)
+    }
+}
+
+/// The netmask of a `prefix` bit network part in a `width` bit address
+fn mask<T>(prefix: u8, width: u32) -> Option<T>
+where
+    T: From<u8> + std::ops::Not<Output = T> + std::ops::Shl<u32, Output = T>,
+{
+    match u32::from(prefix) {
+        0 => Some(T::from(0)),
+        p if p <= width => Some(!T::from(0) << (width - p)),
+        _ => None,
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("Invalid IP address in {}", s))?;
+        let width = match addr.to_canonical() {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .map_err(|_| format!("Invalid prefix length in {}", s))?,
+            None => width,
+        };
+        Subnet::new(addr, prefix).ok_or_else(|| format!("Prefix length too long in {}", s))
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl Serialize for Subnet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Subnet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BanList {
+    subnets: BTreeSet<Subnet>,
+}
+
+impl BanList {
+    /// Read the ban list of `dir`, which is empty if none was saved yet
+    pub fn load(dir: &Path) -> io::Result<BanList> {
+        match fs::read_to_string(dir.join(BAN_FILE_NAME)) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(io::Error::other),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BanList::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write the ban list to `dir`, replacing the old file atomically so a node reading it never
+    /// sees a partial list
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let path = dir.join(BAN_FILE_NAME);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Returns false if `subnet` was already banned
+    pub fn ban(&mut self, subnet: Subnet) -> bool {
+        self.subnets.insert(subnet)
+    }
+
+    /// Returns false if `subnet` wasn't banned
+    pub fn unban(&mut self, subnet: &Subnet) -> bool {
+        self.subnets.remove(subnet)
+    }
+
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.subnets.iter().any(|subnet| subnet.contains(addr))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Subnet> {
+        self.subnets.iter()
+    }
+}
+
+/// The ban list of a data directory, reloaded on `refresh` when the file has changed
+#[derive(Debug)]
+pub struct BanFile {
+    dir: PathBuf,
+    modified: Option<SystemTime>,
+    bans: BanList,
+}
+
+impl BanFile {
+    pub fn open(dir: &Path) -> io::Result<BanFile> {
+        Ok(BanFile {
+            dir: dir.to_path_buf(),
+            modified: modified(dir),
+            bans: BanList::load(dir)?,
+        })
+    }
+
+    /// Reload the bans if the file changed since they were read. If it can't be reloaded the
+    /// previous bans stay in force.
+    pub fn refresh(&mut self) {
+        let modified = modified(&self.dir);
+        if modified != self.modified {
+            if let Ok(bans) = BanList::load(&self.dir) {
+                self.bans = bans;
+                self.modified = modified;
+            }
+        }
+    }
+
+    /// Whether `addr` is banned, as of the last `refresh`
+    pub fn is_banned(&self, addr: IpAddr) -> bool {
+        self.bans.is_banned(addr)
+    }
+}
+
+fn modified(dir: &Path) -> Option<SystemTime> {
+    fs::metadata(dir.join(BAN_FILE_NAME))
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    fn subnet(s: &str) -> Subnet {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn it_parses_subnets() {
+        assert_eq!(subnet("203.0.113.7/24").to_string(), "203.0.113.0/24");
+        assert_eq!(subnet("203.0.113.7").to_string(), "203.0.113.7/32");
+        assert_eq!(subnet("2001:db8::1/32").to_string(), "2001:db8::/32");
+        assert_eq!(subnet("::ffff:203.0.113.7").to_string(), "203.0.113.7/32");
+        assert_eq!(subnet("0.0.0.0/0").to_string(), "0.0.0.0/0");
+        assert!("203.0.113.7/33".parse::<Subnet>().is_err());
+        assert!("203.0.113/24".parse::<Subnet>().is_err());
+        assert!("203.0.113.7/x".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn it_bans_addresses_in_banned_subnets() {
+        let mut bans = BanList::default();
+        assert!(bans.ban(subnet("203.0.113.0/24")));
+        assert!(!bans.ban(subnet("203.0.113.9/24")));
+        assert!(bans.ban(subnet("2001:db8::/32")));
+
+        assert!(bans.is_banned("203.0.113.200".parse().unwrap()));
+        assert!(bans.is_banned("::ffff:203.0.113.200".parse().unwrap()));
+        assert!(bans.is_banned("2001:db8:1::1".parse().unwrap()));
+        assert!(!bans.is_banned("203.0.114.1".parse().unwrap()));
+        assert!(!bans.is_banned("2001:db9::1".parse().unwrap()));
+
+        assert!(bans.unban(&subnet("203.0.113.0/24")));
+        assert!(!bans.unban(&subnet("203.0.113.0/24")));
+        assert!(!bans.is_banned("203.0.113.200".parse().unwrap()));
+    }
+
+    #[test]
+    fn it_persists_bans() {
+        let dir = test_dir("banlist");
+        assert_eq!(BanList::load(&dir).unwrap(), BanList::default());
+
+        let mut file = BanFile::open(&dir).unwrap();
+        assert!(!file.is_banned("198.51.100.1".parse().unwrap()));
+
+        let mut bans = BanList::default();
+        bans.ban(subnet("198.51.100.0/24"));
+        bans.save(&dir).unwrap();
+        assert_eq!(BanList::load(&dir).unwrap(), bans);
+        assert!(!file.is_banned("198.51.100.1".parse().unwrap()));
+        file.refresh();
+        assert!(file.is_banned("198.51.100.1".parse().unwrap()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
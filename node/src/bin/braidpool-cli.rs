@@ -0,0 +1,119 @@
+//! Offline tools for working with exported braids and a node's data directory
+//!
+//! `braidpool-cli analyze <file>` prints summary statistics for a braid saved by
+//! `io_json::save_braid`, or for a fixture in the format described on `io_json::Fixture`. Only
+//! fixtures carry bead timestamps, so the inter-arrival histogram is empty for saved braids.
+//!
+//! `ban`, `unban` and `listbanned` manage the ban list in the data directory, see `banlist`.
+
+use clap::{Parser, Subcommand, ValueEnum};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use node::analysis;
+use node::banlist::{BanList, Subnet};
+use node::braid::io_json::{Dag, Fixture};
+
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print cohort sizes, work per cohort and bead intervals for a braid file
+    Analyze {
+        /// Braid or fixture JSON file
+        file: PathBuf,
+
+        /// JSON gives every statistic, CSV gives one row per cohort for plotting
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+    /// Ban an address or CIDR subnet, e.g. 203.0.113.0/24
+    Ban {
+        subnet: Subnet,
+
+        /// Braid data directory
+        #[arg(long, default_value = "~/.braidpool/")]
+        datadir: PathBuf,
+    },
+    /// Lift a ban made with `ban`
+    Unban {
+        subnet: Subnet,
+
+        /// Braid data directory
+        #[arg(long, default_value = "~/.braidpool/")]
+        datadir: PathBuf,
+    },
+    /// List banned subnets
+    Listbanned {
+        /// Braid data directory
+        #[arg(long, default_value = "~/.braidpool/")]
+        datadir: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Json,
+    Csv,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    match Cli::parse().command {
+        Command::Analyze { file, format } => {
+            let contents = fs::read_to_string(&file)?;
+            let value: serde_json::Value = serde_json::from_str(&contents)?;
+
+            // Fixtures list their beads, saved braids list parents
+            let (dag, timestamps) = if value.get("beads").is_some() {
+                let fixture: Fixture = serde_json::from_value(value)?;
+                (fixture.to_dag()?, fixture.timestamps())
+            } else {
+                let dag: Dag = serde_json::from_value(value)?;
+                (dag, HashMap::new())
+            };
+
+            let summary = analysis::summarize(&dag, &timestamps);
+            match format {
+                Format::Json => println!("{}", serde_json::to_string_pretty(&summary)?),
+                Format::Csv => print!("{}", summary.cohorts_csv()),
+            }
+        }
+        Command::Ban { subnet, datadir } => {
+            let datadir = expand_home(&datadir);
+            let mut bans = BanList::load(&datadir)?;
+            if !bans.ban(subnet) {
+                println!("{} is already banned", subnet);
+            }
+            bans.save(&datadir)?;
+        }
+        Command::Unban { subnet, datadir } => {
+            let datadir = expand_home(&datadir);
+            let mut bans = BanList::load(&datadir)?;
+            if !bans.unban(&subnet) {
+                return Err(format!("{} is not banned", subnet).into());
+            }
+            bans.save(&datadir)?;
+        }
+        Command::Listbanned { datadir } => {
+            for subnet in BanList::load(&expand_home(&datadir))?.iter() {
+                println!("{}", subnet);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Expand a leading `~` like the node does for `--datadir`
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
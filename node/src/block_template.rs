@@ -1,5 +1,9 @@
+use crate::mempool;
+use crate::network::Network;
 use bitcoincore_rpc::RpcApi;
+use bitcoincore_rpc_json::bitcoin::Amount;
 use bitcoincore_rpc_json::{GetBlockTemplateModes, GetBlockTemplateResult, GetBlockTemplateRules};
+use std::collections::VecDeque;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::time::{sleep, Duration};
 
@@ -13,10 +17,151 @@ const BLOCK_TEMPLATE_RULES: [GetBlockTemplateRules; 4] = [
 const BACKOFF_BASE: u64 = 2;
 const MAX_RPC_FAILURES: u32 = 20;
 
+/// Number of recent blocks the expected fee income is averaged over (about a day)
+const FEE_WINDOW: usize = 144;
+
+/// Blocks bitcoind's chain may trail its best known header before its templates are stale
+pub const MAX_HEADER_LAG: u64 = 2;
+
+/// Whether bitcoind is synced well enough for its templates to be worth mining on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendStatus {
+    Synced,
+    InitialBlockDownload,
+    BehindHeaders { blocks: u64, headers: u64 },
+}
+
+impl BackendStatus {
+    pub fn new(initial_block_download: bool, blocks: u64, headers: u64) -> BackendStatus {
+        if initial_block_download {
+            BackendStatus::InitialBlockDownload
+        } else if headers.saturating_sub(blocks) > MAX_HEADER_LAG {
+            BackendStatus::BehindHeaders { blocks, headers }
+        } else {
+            BackendStatus::Synced
+        }
+    }
+}
+
+/// The income offered by a single block template
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemplateFees {
+    pub height: u64,
+    pub coinbase_value: Amount,
+    pub fees: Amount,
+}
+
+impl TemplateFees {
+    pub fn from_template(block_template: &GetBlockTemplateResult) -> TemplateFees {
+        TemplateFees {
+            height: block_template.height,
+            coinbase_value: block_template.coinbase_value,
+            fees: Amount::from_sat(
+                block_template
+                    .transactions
+                    .iter()
+                    .map(|tx| tx.fee.to_sat())
+                    .sum(),
+            ),
+        }
+    }
+}
+
+/// Blocks between subsidy halvings on `network`
+fn halving_interval(network: Network) -> u64 {
+    match network {
+        Network::Regtest => 150,
+        Network::Main | Network::Testnet4 | Network::Signet | Network::Cpunet => 210_000,
+    }
+}
+
+/// The block subsidy at `height`, following Bitcoin Core's `GetBlockSubsidy`
+pub fn block_subsidy(height: u64, network: Network) -> Amount {
+    let halvings = height / halving_interval(network);
+    if halvings >= 64 {
+        return Amount::ZERO;
+    }
+    Amount::from_sat(Amount::from_int_btc(50).to_sat() >> halvings)
+}
+
+/// Check that a template's coinbase pays exactly the subsidy for its height plus its fees. A
+/// template paying more would produce a block invalid by consensus; one paying less means
+/// bitcoind and this node disagree about the network or the height.
+pub fn check_coinbase_value(template_fees: &TemplateFees, network: Network) -> Result<(), String> {
+    let subsidy = block_subsidy(template_fees.height, network);
+    let expected = subsidy + template_fees.fees;
+    if template_fees.coinbase_value != expected {
+        return Err(format!(
+            "Template at height {} has coinbase value {}, expected {} subsidy + {} fees on {}",
+            template_fees.height,
+            template_fees.coinbase_value,
+            subsidy,
+            template_fees.fees,
+            network
+        ));
+    }
+    Ok(())
+}
+
+/// Rolling window over the fees offered by the templates of the last `FEE_WINDOW` blocks
+#[derive(Debug, Default)]
+pub struct FeeTracker {
+    window: VecDeque<TemplateFees>,
+}
+
+impl FeeTracker {
+    pub fn record(&mut self, template_fees: TemplateFees) {
+        if self.window.len() == FEE_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(template_fees);
+    }
+
+    /// Mean transaction fees per block over the window
+    pub fn expected_fees_per_block(&self) -> Option<Amount> {
+        if self.window.is_empty() {
+            return None;
+        }
+        let total: u64 = self.window.iter().map(|f| f.fees.to_sat()).sum();
+        Some(Amount::from_sat(total / self.window.len() as u64))
+    }
+}
+
+/// Ask bitcoind how far it is synced. Errors are left to `getblocktemplate` to report and retry.
+fn backend_status(rpc: &bitcoincore_rpc::Client) -> BackendStatus {
+    match rpc.get_blockchain_info() {
+        Ok(info) => BackendStatus::new(info.initial_block_download, info.blocks, info.headers),
+        Err(e) => {
+            log::warn!("Error on `getblockchaininfo` RPC: {}", e);
+            BackendStatus::Synced
+        }
+    }
+}
+
+/// Fetch a template and send it on, unless bitcoind is still catching up, in which case its
+/// template would be for a stale height and no job is issued until the next block notification.
+/// Returns the template sent, if any. Fails if `getblocktemplate` keeps failing or the consumer
+/// has stopped, leaving it to the supervisor to restart the pipeline.
 pub async fn fetcher(
     rpc: &bitcoincore_rpc::Client,
     block_template_tx: Sender<GetBlockTemplateResult>,
-) {
+) -> Result<Option<GetBlockTemplateResult>, String> {
+    match backend_status(rpc) {
+        BackendStatus::Synced => {}
+        BackendStatus::InitialBlockDownload => {
+            log::warn!("bitcoind is in initial block download, pausing block templates");
+            return Ok(None);
+        }
+        BackendStatus::BehindHeaders { blocks, headers } => {
+            log::warn!(
+                "bitcoind has blocks up to {} but headers up to {}, pausing block templates",
+                blocks,
+                headers
+            );
+            return Ok(None);
+        }
+    }
+
     let mut rpc_failure_counter = 0;
     let mut rpc_failure_backoff;
 
@@ -26,17 +171,17 @@ pub async fn fetcher(
                 block_template_tx
                     .send(get_block_template_result.clone())
                     .await
-                    .expect("send block template over mpsc channel");
-                break;
+                    .map_err(|_| "Block template consumer stopped".to_string())?;
+                return Ok(Some(get_block_template_result));
             }
             Err(e) => {
                 rpc_failure_counter += 1;
                 if rpc_failure_counter > MAX_RPC_FAILURES {
-                    log::error!(
+                    return Err(format!(
                         "Exceeded the maximum number of failed `getblocktemplate` RPC \
-                    attempts. Halting."
-                    );
-                    std::process::exit(1);
+                        attempts: {}",
+                        e
+                    ));
                 }
                 rpc_failure_backoff = u64::checked_pow(BACKOFF_BASE, rpc_failure_counter.clone())
                     .expect("MAX_RPC_FAILURES doesn't allow overflow; qed");
@@ -55,9 +200,37 @@ pub async fn fetcher(
     }
 }
 
+/// Select transactions from bitcoind's mempool ourselves and log how the result compares to
+/// `block_template`, as just fetched by `fetcher`. The mempool is read after the template, so
+/// small differences are expected.
+pub fn compare_standalone_template(
+    rpc: &bitcoincore_rpc::Client,
+    block_template: &GetBlockTemplateResult,
+) {
+    let snapshot = match mempool::snapshot(rpc) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            log::warn!("Error on `getrawmempool` RPC: {}", e);
+            return;
+        }
+    };
+    let selection = mempool::select_transactions(&snapshot, mempool::DEFAULT_MAX_TEMPLATE_WEIGHT);
+
+    let template_fees = TemplateFees::from_template(block_template);
+    log::info!(
+        "Standalone template: {} transactions, {} in fees. `getblocktemplate`: {} transactions, \
+        {} in fees",
+        selection.transactions.len(),
+        Amount::from_sat(selection.fees),
+        block_template.transactions.len(),
+        template_fees.fees
+    );
+}
+
 // dummy placeholder function to consume the received block templates
-pub async fn consumer(mut block_template_rx: Receiver<GetBlockTemplateResult>) {
+pub async fn consumer(mut block_template_rx: Receiver<GetBlockTemplateResult>, network: Network) {
     let mut last_block_template_height = 0;
+    let mut fee_tracker = FeeTracker::default();
     while let Some(block_template) = block_template_rx.recv().await {
         // if block template is from some outdated exponential backoff RPC, ignore it
         if block_template.height > last_block_template_height {
@@ -65,7 +238,125 @@ pub async fn consumer(mut block_template_rx: Receiver<GetBlockTemplateResult>) {
                 "Received new block template via `getblocktemplate` RPC: {:?}",
                 block_template
             );
+
+            let template_fees = TemplateFees::from_template(&block_template);
+            if let Err(e) = check_coinbase_value(&template_fees, network) {
+                log::error!("Refusing block template: {}", e);
+                continue;
+            }
             last_block_template_height = block_template.height;
+            fee_tracker.record(template_fees);
+            log::info!(
+                "Template at height {} offers {} in fees ({} coinbase value). Expected fees \
+                per block over the last {} blocks: {}",
+                template_fees.height,
+                template_fees.fees,
+                template_fees.coinbase_value,
+                fee_tracker.window.len(),
+                fee_tracker
+                    .expected_fees_per_block()
+                    .unwrap_or(Amount::ZERO)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        block_subsidy, check_coinbase_value, Amount, BackendStatus, FeeTracker, Network,
+        TemplateFees, FEE_WINDOW, MAX_HEADER_LAG,
+    };
+
+    fn fees(height: u64, sats: u64) -> TemplateFees {
+        TemplateFees {
+            height,
+            coinbase_value: Amount::from_sat(312_500_000 + sats),
+            fees: Amount::from_sat(sats),
+        }
+    }
+
+    #[test]
+    fn it_averages_fees_per_block() {
+        let mut tracker = FeeTracker::default();
+        assert_eq!(tracker.expected_fees_per_block(), None);
+
+        tracker.record(fees(1, 1000));
+        tracker.record(fees(2, 3000));
+        assert_eq!(
+            tracker.expected_fees_per_block(),
+            Some(Amount::from_sat(2000))
+        );
+    }
+
+    #[test]
+    fn it_only_keeps_the_fee_window() {
+        let mut tracker = FeeTracker::default();
+        tracker.record(fees(0, 1_000_000));
+        for height in 1..=FEE_WINDOW as u64 {
+            tracker.record(fees(height, 100));
         }
+        assert_eq!(
+            tracker.expected_fees_per_block(),
+            Some(Amount::from_sat(100))
+        );
+    }
+
+    #[test]
+    fn it_follows_the_subsidy_schedule() {
+        assert_eq!(block_subsidy(0, Network::Main), Amount::from_int_btc(50));
+        assert_eq!(
+            block_subsidy(209_999, Network::Main),
+            Amount::from_int_btc(50)
+        );
+        assert_eq!(
+            block_subsidy(210_000, Network::Main),
+            Amount::from_int_btc(25)
+        );
+        assert_eq!(
+            block_subsidy(840_000, Network::Main),
+            Amount::from_sat(312_500_000)
+        );
+        assert_eq!(
+            block_subsidy(150, Network::Regtest),
+            Amount::from_int_btc(25)
+        );
+        assert_eq!(block_subsidy(64 * 210_000, Network::Main), Amount::ZERO);
+    }
+
+    #[test]
+    fn it_checks_the_coinbase_value() {
+        let mut template_fees = TemplateFees {
+            height: 840_000,
+            coinbase_value: Amount::from_sat(312_501_000),
+            fees: Amount::from_sat(1000),
+        };
+        assert!(check_coinbase_value(&template_fees, Network::Main).is_ok());
+
+        template_fees.coinbase_value = Amount::from_sat(312_501_001);
+        assert!(check_coinbase_value(&template_fees, Network::Main).is_err());
+
+        template_fees.coinbase_value = Amount::from_int_btc(50) + template_fees.fees;
+        assert!(check_coinbase_value(&template_fees, Network::Main).is_err());
+    }
+
+    #[test]
+    fn it_detects_a_stale_backend() {
+        assert_eq!(BackendStatus::new(false, 100, 100), BackendStatus::Synced);
+        assert_eq!(
+            BackendStatus::new(false, 100, 100 + MAX_HEADER_LAG),
+            BackendStatus::Synced
+        );
+        assert_eq!(
+            BackendStatus::new(false, 100, 101 + MAX_HEADER_LAG),
+            BackendStatus::BehindHeaders {
+                blocks: 100,
+                headers: 101 + MAX_HEADER_LAG
+            }
+        );
+        assert_eq!(
+            BackendStatus::new(true, 100, 100),
+            BackendStatus::InitialBlockDownload
+        );
     }
 }
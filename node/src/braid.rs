@@ -490,6 +490,33 @@ pub fn highest_work_path(
     hwpath
 }
 
+/// Find the last cohort which is final: buried under at least `min_depth` later cohorts that
+/// together contain at least `min_work`. Everything up to and including that cohort can be
+/// treated as settled, e.g. for payouts or pruning. Returns the index into `cohorts`, which must
+/// be ordered from genesis, or `None` if no cohort is final yet.
+#[allow(dead_code)]
+pub fn finalized_up_to(
+    cohorts: &[HashSet<BeadHash>],
+    bead_work: &BeadWork,
+    min_depth: usize,
+    min_work: &Work,
+) -> Option<usize> {
+    let mut buried_work = Work::from(0u64);
+
+    // Walk back from the tip cohort, accumulating the work burying each cohort
+    for (depth, index) in (0..cohorts.len()).rev().enumerate() {
+        if depth >= min_depth && buried_work >= *min_work {
+            return Some(index);
+        }
+        buried_work += cohorts[index]
+            .iter()
+            .map(|b| bead_work.get(b).cloned().unwrap_or_default())
+            .sum::<Work>();
+    }
+
+    None
+}
+
 /// Number the beads in a braid sequentially in topological order starting at genesis
 #[allow(dead_code)]
 pub fn number_beads(hashed_parents: &Relatives) -> Relatives {
@@ -7,9 +7,11 @@ use std::{
 };
 
 use num::BigUint;
+use rand::{seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
 
 use crate::braid::*;
+use crate::target::work_from_target;
 
 /// The work per bead if work is not passed
 fn fixed_bead_work() -> Work {
@@ -243,7 +245,15 @@ pub fn make_dag(
     bead_work: Option<&HashMap<BeadHash, Work>>,
     description: Option<&str>,
 ) -> Dag {
-    let parents = number_beads(hashed_parents);
+    dag_from_numbered_parents(number_beads(hashed_parents), bead_work, description)
+}
+
+/// Like `make_dag`, but keeps the bead hashes of `parents` as they are
+fn dag_from_numbered_parents(
+    parents: Relatives,
+    bead_work: Option<&HashMap<BeadHash, Work>>,
+    description: Option<&str>,
+) -> Dag {
     let children = reverse(&parents);
     let geneses = geneses(&parents);
     let tips = tips(&parents, Some(&children));
@@ -273,6 +283,169 @@ pub fn make_dag(
     }
 }
 
+/// A bead in a fixture, referring to its parents by name
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FixtureBead {
+    pub name: String,
+
+    /// Names of the parents, which must appear earlier in the fixture
+    #[serde(default)]
+    pub parents: Vec<String>,
+
+    /// Target as a decimal string. Beads without a target have a work of 1.
+    #[serde(default)]
+    pub target: Option<String>,
+
+    /// Timestamp in seconds since the epoch
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+/// A hand-written braid for tests, benchmarks and the simulator
+///
+/// ```json
+/// {
+///     "description": "A diamond where the right side has more work",
+///     "beads": [
+///         {"name": "genesis", "timestamp": 1700000000},
+///         {"name": "left", "parents": ["genesis"]},
+///         {"name": "right", "parents": ["genesis"], "target": "1000"},
+///         {"name": "tip", "parents": ["left", "right"], "timestamp": 1700000030}
+///     ]
+/// }
+/// ```
+///
+/// Beads are listed in topological order and numbered in that order starting at 0, so the bead
+/// hashes in the resulting `Dag` are stable across runs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Fixture {
+    #[serde(default)]
+    pub description: Option<String>,
+    pub beads: Vec<FixtureBead>,
+}
+
+impl Fixture {
+    /// The bead hash assigned to each bead name
+    pub fn bead_ids(&self) -> HashMap<String, BeadHash> {
+        self.beads
+            .iter()
+            .enumerate()
+            .map(|(i, bead)| (bead.name.clone(), BeadHash::from(i)))
+            .collect()
+    }
+
+    /// Timestamps of the beads which have one
+    pub fn timestamps(&self) -> HashMap<BeadHash, u64> {
+        self.beads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, bead)| Some((BeadHash::from(i), bead.timestamp?)))
+            .collect()
+    }
+
+    /// Build the DAG, computing bead work from the targets
+    pub fn to_dag(&self) -> Result<Dag, Box<dyn Error>> {
+        let mut ids: HashMap<&str, BeadHash> = HashMap::new();
+        let mut parents = Relatives::new();
+        let mut bead_work = BeadWork::new();
+
+        for (i, bead) in self.beads.iter().enumerate() {
+            let id = BeadHash::from(i);
+            let mut parent_set = HashSet::new();
+            for parent in &bead.parents {
+                match ids.get(parent.as_str()) {
+                    Some(parent_id) => parent_set.insert(parent_id.clone()),
+                    None => {
+                        return Err(format!(
+                            "Bead {} has unknown parent {}, parents must be listed first",
+                            bead.name, parent
+                        )
+                        .into())
+                    }
+                };
+            }
+
+            let work = match &bead.target {
+                Some(target) => {
+                    let target: Target = target
+                        .parse()
+                        .map_err(|_| format!("Bead {} has invalid target {}", bead.name, target))?;
+                    work_from_target(&target)
+                }
+                None => fixed_bead_work(),
+            };
+
+            if ids.insert(&bead.name, id.clone()).is_some() {
+                return Err(format!("Duplicate bead name {}", bead.name).into());
+            }
+            parents.insert(id.clone(), parent_set);
+            bead_work.insert(id, work);
+        }
+
+        Ok(dag_from_numbered_parents(
+            parents,
+            Some(&bead_work),
+            self.description.as_deref(),
+        ))
+    }
+}
+
+/// Load a JSON fixture file in the format described on `Fixture`
+#[allow(dead_code)]
+pub fn load_fixture<P: AsRef<Path>>(filename: P) -> Result<Fixture, Box<dyn Error>> {
+    let mut file = File::open(filename)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    let fixture: Fixture = serde_json::from_str(&contents)?;
+    Ok(fixture)
+}
+
+/// Generate a random braid whose cohorts have the given widths, in order from genesis
+///
+/// The first bead of each cohort has exactly the tips of the previous cohort as parents and no
+/// children within its own cohort, which keeps the cohort from splitting. Each remaining bead
+/// either also has the previous tips as parents, or a random selection of the other beads of the
+/// cohort. Pass a seeded rng for a reproducible braid.
+#[allow(dead_code)]
+pub fn random_dag<R: Rng>(rng: &mut R, cohort_widths: &[usize], description: Option<&str>) -> Dag {
+    let mut parents = Relatives::new();
+    let mut previous_tips: Vec<BeadHash> = Vec::new();
+    let mut next_id: u64 = 0;
+
+    for &width in cohort_widths.iter().filter(|&&w| w > 0) {
+        let first = BeadHash::from(next_id);
+        parents.insert(first.clone(), previous_tips.iter().cloned().collect());
+        next_id += 1;
+
+        let mut cohort = vec![first];
+        let mut candidates: Vec<BeadHash> = Vec::new();
+        for _ in 1..width {
+            let bead = BeadHash::from(next_id);
+            next_id += 1;
+
+            let bead_parents: HashSet<BeadHash> = if candidates.is_empty() || rng.gen_bool(0.5) {
+                previous_tips.iter().cloned().collect()
+            } else {
+                let count = rng.gen_range(1..=candidates.len());
+                candidates.choose_multiple(rng, count).cloned().collect()
+            };
+
+            parents.insert(bead.clone(), bead_parents);
+            candidates.push(bead.clone());
+            cohort.push(bead);
+        }
+
+        let cohort_set: HashSet<BeadHash> = cohort.iter().cloned().collect();
+        previous_tips = tips(&sub_braid(&cohort_set, &parents), None)
+            .into_iter()
+            .collect();
+        previous_tips.sort();
+    }
+
+    dag_from_numbered_parents(parents, None, description)
+}
+
 /// Load a JSON file containing a braid
 #[allow(dead_code)]
 pub fn load_braid<P: AsRef<Path>>(filename: P) -> Result<Dag, Box<dyn Error>> {
@@ -1,4 +1,5 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use node::network::Network;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
@@ -7,15 +8,32 @@ pub struct Cli {
     #[arg(long, default_value = "~/.braidpool/")]
     pub datadir: PathBuf,
 
-    /// Bind to a given address and always listen on it
+    /// Start even if the data directory is locked by another node. Only use this to recover
+    /// when you are sure no other node is running, e.g. on filesystems without lock support
+    #[arg(long)]
+    pub force: bool,
+
+    /// Which subsystems to run
+    #[arg(long, value_enum, default_value_t = Mode::Full)]
+    pub mode: Mode,
+
+    /// Bind to a given address and always listen on it. Use e.g. "[::]:25188" to listen on IPv6
     #[arg(long, default_value = "0.0.0.0:25188")]
     pub bind: String,
 
     /// Add a node to connect to and attempt to keep the connection open. This option can be
-    /// specified multiple times
+    /// specified multiple times. IPv6 addresses are written as "[::1]:25188"
     #[arg(long)]
     pub addnode: Option<Vec<String>>,
 
+    /// Connect to added nodes through this SOCKS5 proxy, e.g. Tor at 127.0.0.1:9050
+    #[arg(long)]
+    pub proxy: Option<String>,
+
+    /// Connect directly when connecting through the proxy fails, instead of giving up
+    #[arg(long)]
+    pub proxyfallback: bool,
+
     /// Connect to this bitcoin node
     #[arg(long, default_value = "0.0.0.0")]
     pub bitcoin: String,
@@ -32,9 +50,10 @@ pub struct Cli {
     #[arg(long, default_value = "")]
     pub rpcpass: Option<String>,
 
-    /// Which network to use. Valid options are mainnet, testnet4, signet, cpunet (preferred)
+    /// Which network to use. Valid options are mainnet, testnet4, signet, regtest, cpunet
+    /// (preferred)
     #[arg(long, default_value = "main")]
-    pub network: Option<String>,
+    pub network: Network,
 
     /// Use this cookie file for bitcoin RPC
     #[arg(long, default_value = "~/.bitcoin/.cookie")]
@@ -43,4 +62,53 @@ pub struct Cli {
     /// Use this port for bitcoin ZMQ
     #[arg(long, default_value = "28332")]
     pub zmqhashblockport: u16,
+
+    /// Also select transactions ourselves from bitcoind's mempool on every new block, and log
+    /// how the result compares to `getblocktemplate`
+    #[arg(long)]
+    pub standalonetemplates: bool,
+
+    /// Also write logs to rotating files in <datadir>/logs
+    #[arg(long)]
+    pub logfile: bool,
+
+    /// Log filter for the log file, e.g. "debug" or "info,node=debug". The console filter is
+    /// taken from RUST_LOG
+    #[arg(long, default_value = "info")]
+    pub logfilelevel: String,
+
+    /// Rotate the log file once it exceeds this many megabytes
+    #[arg(long, default_value = "100")]
+    pub logmaxsize: u64,
+
+    /// Rotate the log file once it is older than this many hours
+    #[arg(long, default_value = "24")]
+    pub logmaxage: u64,
+
+    /// Number of rotated, gzipped log files to keep
+    #[arg(long, default_value = "10")]
+    pub logkeep: usize,
+}
+
+/// Which subsystems the node runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// Take part in the p2p network and fetch block templates for mining
+    Full,
+    /// Only take part in the p2p network. No bitcoin node is needed
+    Relay,
+    /// Only fetch block templates for mining, connecting to added nodes but not accepting peers
+    Mining,
+}
+
+impl Mode {
+    /// Whether to connect to bitcoind for block templates
+    pub fn mines(self) -> bool {
+        self != Mode::Relay
+    }
+
+    /// Whether to accept inbound peers
+    pub fn listens(self) -> bool {
+        self != Mode::Mining
+    }
 }
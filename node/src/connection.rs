@@ -1,8 +1,11 @@
 use bytes::Bytes;
 use std::{error::Error, net::SocketAddr};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
 //use tokio::sync::mpsc;
 use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
 
 // const CHANNEL_CAPACITY: usize = 32;
 
@@ -11,19 +14,57 @@ use crate::protocol::{self, HandshakeMessage, Message, ProtocolMessage};
 pub struct Connection {
     reader: FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
     writer: FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
+    shutdown: CancellationToken,
     //channel_receiver: mpsc::Receiver<String>,
     //channel_sender: mpsc::Sender<String>,
 }
 
+/// Length-delimited codec for the p2p protocol, refusing frames larger than any valid message
+pub fn codec() -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .max_frame_length(protocol::MAX_MESSAGE_SIZE)
+        .new_codec()
+}
+
+/// Open a TCP connection to `node` ("host:port", IPv4, IPv6 or a hostname), optionally through a
+/// SOCKS5 proxy such as Tor. When proxied the hostname is resolved by the proxy, so `.onion`
+/// addresses work and no DNS lookups leak. If `proxy_fallback` is set, a failed proxy connection
+/// is retried directly.
+pub async fn dial(
+    node: &str,
+    proxy: Option<&str>,
+    proxy_fallback: bool,
+) -> Result<TcpStream, Box<dyn Error>> {
+    let Some(proxy) = proxy else {
+        return Ok(TcpStream::connect(node).await?);
+    };
+
+    match Socks5Stream::connect(proxy, node).await {
+        Ok(stream) => Ok(stream.into_inner()),
+        Err(e) if proxy_fallback => {
+            log::warn!(
+                "Connecting to {} through proxy {} failed ({}), connecting directly",
+                node,
+                proxy,
+                e
+            );
+            Ok(TcpStream::connect(node).await?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
 impl Connection {
     pub fn new(
         reader: FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
         writer: FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
+        shutdown: CancellationToken,
     ) -> Connection {
         //let (channel_sender, channel_receiver) = mpsc::channel(CHANNEL_CAPACITY);
         Connection {
             reader,
             writer,
+            shutdown,
             // channel_receiver,
             // channel_sender,
         }
@@ -44,16 +85,26 @@ impl Connection {
         Ok(())
     }
 
+    /// Read and answer messages until the peer disconnects or `shutdown` is cancelled. A message
+    /// being handled when shutdown starts is finished and its response flushed before returning.
     pub async fn start_read_loop(&mut self) -> Result<(), Box<dyn Error>> {
-        use futures::StreamExt;
+        use futures::{SinkExt, StreamExt};
         log::info!("Start read loop....");
         loop {
-            match self.reader.next().await {
+            let item = tokio::select! {
+                _ = self.shutdown.cancelled() => {
+                    SinkExt::<Bytes>::close(&mut self.writer).await?;
+                    return Ok(());
+                }
+                item = self.reader.next() => item,
+            };
+            match item {
                 None => {
                     return Err("peer closed connection".into());
                 }
                 Some(item) => match item {
-                    Err(_) => {
+                    Err(e) => {
+                        log::warn!("Error reading frame from peer: {}", e);
                         return Err("peer closed connection".into());
                     }
                     Ok(message) => {
@@ -69,7 +120,13 @@ impl Connection {
     async fn message_received(&mut self, message: &Bytes) -> Result<(), &'static str> {
         use futures::SinkExt;
 
-        let message: Message = protocol::Message::from_bytes(message).unwrap();
+        let message: Message = match protocol::Message::from_bytes(message) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!("Invalid message from peer: {}", e);
+                return Err("Error deserializing: Closing peer connection");
+            }
+        };
         match message.response_for_received() {
             Ok(result) => {
                 if let Some(response) = result {
@@ -89,3 +146,31 @@ impl Connection {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn it_stops_reading_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let shutdown = CancellationToken::new();
+        let (r, w) = stream.into_split();
+        let mut conn = Connection::new(
+            FramedRead::new(r, codec()),
+            FramedWrite::new(w, codec()),
+            shutdown.clone(),
+        );
+        let task = tokio::spawn(async move { conn.start_from_accept().await.is_ok() });
+
+        shutdown.cancel();
+        assert!(task.await.unwrap());
+        drop(client);
+    }
+}
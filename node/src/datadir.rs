@@ -0,0 +1,121 @@
+//! Exclusive lock on the data directory, and the network it belongs to
+//!
+//! Only one node may use a data directory at a time. The lock is an advisory `flock` on
+//! `<datadir>/.lock` which the OS releases when the process exits, so a crashed node never leaves
+//! a stale lock behind. The file records the PID of the owner for the error message.
+//!
+//! A data directory also belongs to a single network, recorded in `<datadir>/network` the first
+//! time the node starts, so a mistyped `--network` can't mix data from different networks.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+use crate::network::Network;
+
+const LOCK_FILE_NAME: &str = ".lock";
+const NETWORK_FILE_NAME: &str = "network";
+
+/// Take the lock on `dir`, returning the lock file which must be kept open for as long as the
+/// data directory is in use. With `force` the node starts even if the lock can't be taken, e.g.
+/// when the data directory is on a filesystem without lock support.
+pub fn lock(dir: &Path, force: bool) -> io::Result<File> {
+    let path = dir.join(LOCK_FILE_NAME);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+
+    let error = match file.try_lock() {
+        Ok(()) => {
+            file.set_len(0)?;
+            write!(file, "{}", std::process::id())?;
+            return Ok(file);
+        }
+        Err(TryLockError::WouldBlock) => {
+            let mut owner = String::new();
+            file.read_to_string(&mut owner)?;
+            file.rewind()?;
+            format!(
+                "Data directory {} is in use by another braidpool node (pid {})",
+                dir.display(),
+                owner.trim()
+            )
+        }
+        Err(TryLockError::Error(e)) => {
+            format!("Failed to lock data directory {}: {}", dir.display(), e)
+        }
+    };
+
+    if force {
+        log::warn!("{}. Continuing anyway because of --force", error);
+        Ok(file)
+    } else {
+        Err(io::Error::other(format!(
+            "{}. Stop the other node, choose another --datadir, or pass --force if you are \
+            sure nothing else is using it",
+            error
+        )))
+    }
+}
+
+/// Record `network` as the network of `dir` if it has none yet, otherwise check it matches
+pub fn check_network(dir: &Path, network: Network) -> io::Result<()> {
+    let path = dir.join(NETWORK_FILE_NAME);
+    match fs::read_to_string(&path) {
+        Ok(recorded) if recorded.trim().parse() == Ok(network) => Ok(()),
+        Ok(recorded) => Err(io::Error::other(format!(
+            "Data directory {} belongs to network {}, not {}. Check --network, or use another \
+            --datadir",
+            dir.display(),
+            recorded.trim(),
+            network
+        ))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => fs::write(&path, network.to_string()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn it_refuses_a_locked_datadir() {
+        let dir = test_dir("datadir-locked");
+        let held = lock(&dir, false).unwrap();
+
+        let err = lock(&dir, false).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("pid {}", std::process::id())));
+
+        // Forcing starts anyway, and the lock is free again once the holder is dropped
+        assert!(lock(&dir, true).is_ok());
+        drop(held);
+        assert!(lock(&dir, false).is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn it_refuses_a_different_network() {
+        let dir = test_dir("datadir-network");
+
+        check_network(&dir, Network::Cpunet).unwrap();
+        check_network(&dir, Network::Cpunet).unwrap();
+        let err = check_network(&dir, Network::Main).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("belongs to network cpunet, not main"));
+
+        // Any name of the network matches what was recorded
+        fs::write(dir.join(NETWORK_FILE_NAME), "mainnet").unwrap();
+        check_network(&dir, Network::Main).unwrap();
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
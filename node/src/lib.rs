@@ -1 +1,31 @@
+pub mod analysis;
+pub mod banlist;
 pub mod braid;
+pub mod network;
+pub mod target;
+
+#[cfg(test)]
+mod test_util;
+
+// The node itself, see `service::BraidpoolNode` for embedding it
+#[cfg(feature = "p2p")]
+pub mod connection;
+#[cfg(feature = "p2p")]
+pub mod datadir;
+#[cfg(feature = "p2p")]
+pub mod protocol;
+#[cfg(feature = "p2p")]
+pub mod service;
+
+// Block templates from bitcoind
+#[cfg(feature = "bitcoind")]
+pub mod block_template;
+#[cfg(feature = "bitcoind")]
+pub mod mempool;
+#[cfg(feature = "bitcoind")]
+pub mod rpc;
+#[cfg(feature = "bitcoind")]
+pub mod zmq;
+
+#[cfg(feature = "node")]
+pub mod logfile;
@@ -0,0 +1,187 @@
+//! Rotating log file for the node
+//!
+//! Logs are written to `braidpool.log` in the given directory. Once the file grows past the
+//! configured size or age it is renamed with a millisecond timestamp suffix and gzipped in a
+//! background thread, keeping only the newest `keep` compressed files.
+
+use flate2::{write::GzEncoder, Compression};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const LOG_FILE_NAME: &str = "braidpool.log";
+
+/// When to rotate the active log file, and how many old files to keep
+pub struct RotationPolicy {
+    pub max_size: u64,
+    pub max_age: Duration,
+    pub keep: usize,
+}
+
+pub struct RotatingFileWriter {
+    dir: PathBuf,
+    policy: RotationPolicy,
+    file: File,
+    written: u64,
+    opened_at: Instant,
+}
+
+impl RotatingFileWriter {
+    pub fn new(dir: PathBuf, policy: RotationPolicy) -> io::Result<RotatingFileWriter> {
+        fs::create_dir_all(&dir)?;
+        let (file, written) = open_log_file(&dir)?;
+        Ok(RotatingFileWriter {
+            dir,
+            policy,
+            file,
+            written,
+            opened_at: Instant::now(),
+        })
+    }
+
+    fn needs_rotation(&self, incoming: usize) -> bool {
+        self.written > 0
+            && (self.written + incoming as u64 > self.policy.max_size
+                || self.opened_at.elapsed() >= self.policy.max_age)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let rotated = self.dir.join(format!("{}.{}", LOG_FILE_NAME, timestamp));
+        fs::rename(self.dir.join(LOG_FILE_NAME), &rotated)?;
+
+        let (file, written) = open_log_file(&self.dir)?;
+        self.file = file;
+        self.written = written;
+        self.opened_at = Instant::now();
+
+        // We can't log from inside the log writer, so failures go to stderr
+        let dir = self.dir.clone();
+        let keep = self.policy.keep;
+        std::thread::spawn(move || {
+            if let Err(e) = compress(&rotated) {
+                eprintln!("Failed to compress log file {}: {}", rotated.display(), e);
+            }
+            if let Err(e) = prune(&dir, keep) {
+                eprintln!("Failed to remove old log files: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl Write for RotatingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.needs_rotation(buf.len()) {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_log_file(dir: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(LOG_FILE_NAME))?;
+    let written = file.metadata()?.len();
+    Ok((file, written))
+}
+
+/// Gzip `path` to `path.gz` and remove the uncompressed file
+fn compress(path: &Path) -> io::Result<()> {
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(gz_path)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
+
+/// Remove all but the newest `keep` compressed log files
+fn prune(dir: &Path, keep: usize) -> io::Result<()> {
+    let prefix = format!("{}.", LOG_FILE_NAME);
+    let mut archives: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".gz"))
+        })
+        .collect();
+
+    // Timestamps have the same number of digits, so name order is age order
+    archives.sort();
+    let excess = archives.len().saturating_sub(keep);
+    for path in archives.into_iter().take(excess) {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn it_rotates_when_the_size_limit_is_exceeded() {
+        let dir = test_dir("logfile-rotate");
+        let mut writer = RotatingFileWriter::new(
+            dir.clone(),
+            RotationPolicy {
+                max_size: 16,
+                max_age: Duration::from_secs(3600),
+                keep: 10,
+            },
+        )
+        .unwrap();
+
+        writer.write_all(b"first line 1234\n").unwrap();
+        writer.write_all(b"second line\n").unwrap();
+        writer.flush().unwrap();
+
+        let active = fs::read(dir.join(LOG_FILE_NAME)).unwrap();
+        assert_eq!(active, b"second line\n");
+        // The rotated file may or may not have been compressed yet
+        let rotated = fs::read_dir(&dir).unwrap().any(|e| {
+            let name = e.unwrap().file_name();
+            name.to_str().unwrap().starts_with("braidpool.log.")
+        });
+        assert!(rotated);
+
+        // Compression may still be running in the background
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn it_keeps_only_the_newest_archives() {
+        let dir = test_dir("logfile-prune");
+        for timestamp in ["1000", "2000", "3000"] {
+            File::create(dir.join(format!("{}.{}.gz", LOG_FILE_NAME, timestamp))).unwrap();
+        }
+
+        prune(&dir, 2).unwrap();
+
+        assert!(!dir.join(format!("{}.1000.gz", LOG_FILE_NAME)).exists());
+        assert!(dir.join(format!("{}.2000.gz", LOG_FILE_NAME)).exists());
+        assert!(dir.join(format!("{}.3000.gz", LOG_FILE_NAME)).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,27 +1,21 @@
 use clap::Parser;
+use node::logfile;
+use node::service::{BitcoinConfig, BraidpoolNode};
 use std::error::Error;
 use std::fs;
-use std::net::ToSocketAddrs;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::mpsc;
-use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
 
-mod block_template;
-mod braid;
 mod cli;
-mod connection;
-mod protocol;
-mod rpc;
-mod zmq;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = cli::Cli::parse();
 
-    setup_logging();
-    setup_tracing()?;
-
     let datadir = shellexpand::full(args.datadir.to_str().unwrap()).unwrap();
+    setup_tracing(&args, &datadir)?;
+
     match fs::metadata(&*datadir) {
         Ok(m) => {
             if !m.is_dir() {
@@ -35,82 +29,91 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    let rpc = rpc::setup(
-        args.bitcoin.clone(),
-        args.rpcport,
-        args.rpcuser,
-        args.rpcpass,
-        args.rpccookie,
-    )?;
-    let zmq_url = format!("tcp://{}:{}", args.bitcoin, args.zmqhashblockport);
-
-    let (block_template_tx, block_template_rx) = mpsc::channel(1);
-    tokio::spawn(zmq::zmq_hashblock_listener(zmq_url, rpc, block_template_tx));
-    tokio::spawn(block_template::consumer(block_template_rx));
-
-    if let Some(addnode) = args.addnode {
-        for node in addnode.iter() {
-            //log::info!("Connecting to node: {:?}", node);
-            let stream = TcpStream::connect(node).await.expect("Error connecting");
-            let (r, w) = stream.into_split();
-            let framed_reader = FramedRead::new(r, LengthDelimitedCodec::new());
-            let framed_writer = FramedWrite::new(w, LengthDelimitedCodec::new());
-            let mut conn = connection::Connection::new(framed_reader, framed_writer);
-            if let Ok(addr_iter) = node.to_socket_addrs() {
-                if let Some(addr) = addr_iter.into_iter().next() {
-                    tokio::spawn(async move {
-                        if conn.start_from_connect(&addr).await.is_err() {
-                            log::warn!("Peer {} closed connection", addr)
-                        }
-                    });
-                }
-            }
-        }
+    log::info!("Running in {:?} mode", args.mode);
+    let mut builder = BraidpoolNode::builder()
+        .datadir(&*datadir)
+        .force(args.force)
+        .network(args.network);
+    if args.mode.mines() {
+        builder = builder.bitcoin(BitcoinConfig {
+            host: args.bitcoin.clone(),
+            rpc_port: args.rpcport,
+            rpc_user: args.rpcuser.clone(),
+            rpc_pass: args.rpcpass.clone(),
+            rpc_cookie: args.rpccookie.clone(),
+            zmq_hashblock_port: args.zmqhashblockport,
+            standalone_templates: args.standalonetemplates,
+        });
+    }
+    if args.mode.listens() {
+        builder = builder.bind(&args.bind);
+    }
+    for node in args.addnode.iter().flatten() {
+        builder = builder.addnode(node);
+    }
+    if let Some(proxy) = &args.proxy {
+        builder = builder.proxy(proxy, args.proxyfallback);
     }
 
-    log::info!("Binding to {}", args.bind);
-    let listener = TcpListener::bind(&args.bind).await?;
-    loop {
-        // Asynchronously wait for an inbound TcpStream.
-        log::info!("Starting accept");
-        match listener.accept().await {
-            Ok((stream, _)) => {
-                let addr = stream.peer_addr()?;
-                log::info!("Accepted connection from {}", addr);
-                let (r, w) = stream.into_split();
-                let framed_reader = FramedRead::new(r, LengthDelimitedCodec::new());
-                let framed_writer = FramedWrite::new(w, LengthDelimitedCodec::new());
-                let mut conn = connection::Connection::new(framed_reader, framed_writer);
+    let handle = builder.build()?.start().await?;
+    shutdown_signal().await?;
+    handle.stop().await;
 
-                tokio::spawn(async move {
-                    if conn.start_from_accept().await.is_err() {
-                        log::warn!("Peer {} closed connection", addr)
-                    }
-                });
-            }
-            Err(e) => log::error!("couldn't get client: {:?}", e),
+    Ok(())
+}
+
+/// Wait for Ctrl-C, or on Unix for SIGTERM, which is how service managers and container runtimes
+/// stop the node
+async fn shutdown_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
         }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
 }
 
-fn setup_logging() {
-    env_logger::init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, "info"),
-    );
-}
+fn setup_tracing(args: &cli::Cli, datadir: &str) -> Result<(), Box<dyn Error>> {
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::EnvFilter;
 
-fn setup_tracing() -> Result<(), Box<dyn Error>> {
-    // Create a filter for controlling the verbosity of tracing output
-    let filter =
-        tracing_subscriber::EnvFilter::from_default_env().add_directive("chat=info".parse()?);
+    // Create a filter for controlling the verbosity of console output, defaulting to info
+    let console_filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new("info"))
+        .add_directive("chat=info".parse()?);
+    let console_layer = tracing_subscriber::fmt::layer().with_filter(console_filter);
 
-    // Build a `tracing` subscriber with the specified filter
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_env_filter(filter)
-        .finish();
+    // Optionally mirror output into a rotating log file with its own filter
+    let file_layer = if args.logfile {
+        let writer = logfile::RotatingFileWriter::new(
+            Path::new(datadir).join("logs"),
+            logfile::RotationPolicy {
+                max_size: args.logmaxsize * 1024 * 1024,
+                max_age: Duration::from_secs(args.logmaxage * 60 * 60),
+                keep: args.logkeep,
+            },
+        )?;
+        Some(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(writer))
+                .with_filter(EnvFilter::try_new(&args.logfilelevel)?),
+        )
+    } else {
+        None
+    };
 
-    // Set the subscriber as the global default for tracing
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    // Set the subscriber as the global default. This also forwards records from the `log`
+    // macros into `tracing`, so both end up in the console and the log file.
+    tracing_subscriber::registry()
+        .with(console_layer)
+        .with(file_layer)
+        .try_init()?;
 
     Ok(())
 }
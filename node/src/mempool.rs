@@ -0,0 +1,271 @@
+//! Standalone transaction selection from a mempool snapshot
+//!
+//! Rather than relying on the template `getblocktemplate` builds, we can select transactions
+//! ourselves from `getrawmempool true`. Selection follows Bitcoin Core's ancestor feerate
+//! algorithm: repeatedly include the transaction whose package (the transaction plus all of its
+//! not yet included in-mempool ancestors) pays the highest feerate, until the block is full. This
+//! is the starting point for pool policies that differ from Bitcoin Core's.
+
+use bitcoincore_rpc::RpcApi;
+use bitcoincore_rpc_json::bitcoin::Txid;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::Hash;
+
+/// Block weight available for transactions, leaving room for the header and coinbase like
+/// Bitcoin Core's default `-blockmaxweight`
+pub const DEFAULT_MAX_TEMPLATE_WEIGHT: u64 = 3_996_000;
+
+/// A transaction in a mempool snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolTx<K> {
+    /// Fee in satoshis, including any priority delta
+    pub fee: u64,
+    pub weight: u64,
+    /// In-mempool parents of this transaction
+    pub depends: Vec<K>,
+}
+
+/// The transactions chosen for a template, parents before children
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection<K> {
+    pub transactions: Vec<K>,
+    pub fees: u64,
+    pub weight: u64,
+}
+
+/// Fetch a snapshot of bitcoind's mempool
+pub fn snapshot(
+    rpc: &bitcoincore_rpc::Client,
+) -> Result<HashMap<Txid, MempoolTx<Txid>>, bitcoincore_rpc::Error> {
+    Ok(rpc
+        .get_raw_mempool_verbose()?
+        .into_iter()
+        .map(|(txid, entry)| {
+            let tx = MempoolTx {
+                fee: entry.fees.modified.to_sat(),
+                weight: entry.weight.unwrap_or(entry.vsize * 4),
+                depends: entry.depends,
+            };
+            (txid, tx)
+        })
+        .collect())
+}
+
+/// A candidate package in the selection queue. `version` tells stale entries apart after the
+/// package shrank because some of its ancestors were included.
+struct Candidate<K> {
+    fee: u64,
+    weight: u64,
+    txid: K,
+    version: u32,
+}
+
+impl<K: Ord> Ord for Candidate<K> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Compare fee / weight without dividing
+        let lhs = self.fee as u128 * other.weight as u128;
+        let rhs = other.fee as u128 * self.weight as u128;
+        lhs.cmp(&rhs)
+            .then_with(|| other.txid.cmp(&self.txid))
+            .then_with(|| self.version.cmp(&other.version))
+    }
+}
+
+impl<K: Ord> PartialOrd for Candidate<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord> PartialEq for Candidate<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<K: Ord> Eq for Candidate<K> {}
+
+/// Select transactions by ancestor feerate until `max_weight` is reached. Parents which are not
+/// in `mempool` are assumed to be confirmed.
+pub fn select_transactions<K>(mempool: &HashMap<K, MempoolTx<K>>, max_weight: u64) -> Selection<K>
+where
+    K: Clone + Eq + Hash + Ord,
+{
+    let mut ancestors: HashMap<K, HashSet<K>> = HashMap::new();
+    for txid in mempool.keys() {
+        collect_ancestors(txid, mempool, &mut ancestors);
+    }
+
+    let mut descendants: HashMap<&K, Vec<&K>> = HashMap::new();
+    for (txid, txid_ancestors) in &ancestors {
+        for ancestor in txid_ancestors {
+            descendants.entry(ancestor).or_default().push(txid);
+        }
+    }
+
+    // Fee and weight of each transaction's package of not yet included ancestors
+    let mut packages: HashMap<&K, (u64, u64, u32)> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for (txid, tx) in mempool {
+        let (fee, weight) = ancestors[txid]
+            .iter()
+            .map(|a| &mempool[a])
+            .fold((tx.fee, tx.weight), |(fee, weight), a| {
+                (fee + a.fee, weight + a.weight)
+            });
+        packages.insert(txid, (fee, weight, 0));
+        queue.push(Candidate {
+            fee,
+            weight,
+            txid: txid.clone(),
+            version: 0,
+        });
+    }
+
+    let mut selection = Selection {
+        transactions: Vec::new(),
+        fees: 0,
+        weight: 0,
+    };
+    let mut included: HashSet<K> = HashSet::new();
+
+    while let Some(candidate) = queue.pop() {
+        if included.contains(&candidate.txid)
+            || packages[&candidate.txid].2 != candidate.version
+            || selection.weight + candidate.weight > max_weight
+        {
+            continue;
+        }
+
+        // Include the package parents first. Within a package, a transaction has more
+        // ancestors than any of its own ancestors.
+        let mut package: Vec<&K> = ancestors[&candidate.txid]
+            .iter()
+            .filter(|a| !included.contains(*a))
+            .chain(std::iter::once(&candidate.txid))
+            .collect();
+        package.sort_by_key(|txid| (ancestors[*txid].len(), *txid));
+
+        for txid in package {
+            let tx = &mempool[txid];
+            included.insert(txid.clone());
+            selection.transactions.push(txid.clone());
+            selection.fees += tx.fee;
+            selection.weight += tx.weight;
+
+            // Descendants no longer need to pay for this transaction
+            for descendant in descendants.get(txid).into_iter().flatten() {
+                if included.contains(*descendant) {
+                    continue;
+                }
+                let package = packages
+                    .get_mut(*descendant)
+                    .expect("all packages are known");
+                package.0 -= tx.fee;
+                package.1 -= tx.weight;
+                package.2 += 1;
+                queue.push(Candidate {
+                    fee: package.0,
+                    weight: package.1,
+                    txid: (*descendant).clone(),
+                    version: package.2,
+                });
+            }
+        }
+    }
+
+    selection
+}
+
+/// Fill `ancestors` with the in-mempool ancestors of `txid` and of everything it depends on
+fn collect_ancestors<K>(
+    txid: &K,
+    mempool: &HashMap<K, MempoolTx<K>>,
+    ancestors: &mut HashMap<K, HashSet<K>>,
+) where
+    K: Clone + Eq + Hash,
+{
+    if ancestors.contains_key(txid) {
+        return;
+    }
+
+    let mut result = HashSet::new();
+    for parent in &mempool[txid].depends {
+        if !mempool.contains_key(parent) {
+            continue;
+        }
+        collect_ancestors(parent, mempool, ancestors);
+        result.insert(parent.clone());
+        result.extend(ancestors[parent].iter().cloned());
+    }
+    ancestors.insert(txid.clone(), result);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tx(fee: u64, weight: u64, depends: &[u32]) -> MempoolTx<u32> {
+        MempoolTx {
+            fee,
+            weight,
+            depends: depends.to_vec(),
+        }
+    }
+
+    #[test]
+    fn it_selects_by_feerate() {
+        let mempool = HashMap::from([
+            (1, tx(1000, 400, &[])),
+            (2, tx(4000, 400, &[])),
+            (3, tx(2000, 400, &[])),
+        ]);
+
+        let selection = select_transactions(&mempool, 800);
+        assert_eq!(selection.transactions, vec![2, 3]);
+        assert_eq!(selection.fees, 6000);
+        assert_eq!(selection.weight, 800);
+    }
+
+    #[test]
+    fn it_lets_children_pay_for_parents() {
+        // 1 alone pays less than 2, but 1 and its child 3 together pay more
+        let mempool = HashMap::from([
+            (1, tx(100, 400, &[])),
+            (2, tx(2000, 400, &[])),
+            (3, tx(10000, 400, &[1])),
+        ]);
+
+        let selection = select_transactions(&mempool, 800);
+        assert_eq!(selection.transactions, vec![1, 3]);
+        assert_eq!(selection.fees, 10100);
+    }
+
+    #[test]
+    fn it_orders_parents_before_children() {
+        let mempool = HashMap::from([
+            (1, tx(100, 400, &[])),
+            (2, tx(100, 400, &[1])),
+            (3, tx(100000, 400, &[2])),
+            // Confirmed parents are not in the mempool
+            (4, tx(500, 400, &[99])),
+        ]);
+
+        let selection = select_transactions(&mempool, DEFAULT_MAX_TEMPLATE_WEIGHT);
+        assert_eq!(selection.transactions, vec![1, 2, 3, 4]);
+        assert_eq!(selection.weight, 1600);
+    }
+
+    #[test]
+    fn it_skips_packages_which_do_not_fit() {
+        let mempool = HashMap::from([
+            (1, tx(10000, 1000, &[])),
+            (2, tx(1000, 400, &[])),
+            (3, tx(100, 400, &[])),
+        ]);
+
+        let selection = select_transactions(&mempool, 900);
+        assert_eq!(selection.transactions, vec![2, 3]);
+    }
+}
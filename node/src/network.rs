@@ -0,0 +1,70 @@
+//! The bitcoin network a node runs on
+//!
+//! `--network` is parsed into a `Network` once at startup, so a mistyped name is refused there
+//! instead of silently getting mainnet's consensus rules.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Network {
+    Main,
+    Testnet4,
+    Signet,
+    Regtest,
+    Cpunet,
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "main" | "mainnet" => Ok(Network::Main),
+            "testnet4" => Ok(Network::Testnet4),
+            "signet" => Ok(Network::Signet),
+            "regtest" => Ok(Network::Regtest),
+            "cpunet" => Ok(Network::Cpunet),
+            _ => Err(format!(
+                "Unknown network {}. Valid networks are mainnet, testnet4, signet, regtest and \
+                cpunet",
+                s
+            )),
+        }
+    }
+}
+
+/// Written with the name bitcoind uses, which is also how the data directory records it
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Network::Main => "main",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+            Network::Cpunet => "cpunet",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_known_networks_only() {
+        for network in [
+            Network::Main,
+            Network::Testnet4,
+            Network::Signet,
+            Network::Regtest,
+            Network::Cpunet,
+        ] {
+            assert_eq!(network.to_string().parse(), Ok(network));
+        }
+        assert_eq!("mainnet".parse(), Ok(Network::Main));
+        assert!("testnet".parse::<Network>().is_err());
+        assert!("Main".parse::<Network>().is_err());
+    }
+}
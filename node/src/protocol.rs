@@ -15,6 +15,12 @@ pub use handshake::HandshakeMessage;
 pub use heartbeat::HeartbeatMessage;
 pub use ping::PingMessage;
 
+/// Largest frame we accept from a peer, for any message type
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// Largest encoding of the small control messages (ping, handshake, heartbeat)
+const MAX_CONTROL_MESSAGE_SIZE: usize = 1024;
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum Message {
     Ping(PingMessage),
@@ -30,7 +36,23 @@ impl Message {
     }
 
     pub fn from_bytes(b: &[u8]) -> Result<Self, Box<dyn Error>> {
-        Ok(flexbuffers::from_slice(b)?)
+        if b.len() > MAX_MESSAGE_SIZE {
+            return Err("message exceeds maximum message size".into());
+        }
+        let message: Message = flexbuffers::from_slice(b)?;
+        if b.len() > message.max_size() {
+            return Err("message exceeds maximum size for its type".into());
+        }
+        Ok(message)
+    }
+
+    /// The maximum encoded size accepted for this type of message
+    pub fn max_size(&self) -> usize {
+        match self {
+            Message::Ping(_) | Message::Handshake(_) | Message::Heartbeat(_) => {
+                MAX_CONTROL_MESSAGE_SIZE
+            }
+        }
     }
 
     pub fn response_for_received(&self) -> Result<Option<Message>, &'static str> {
@@ -55,6 +77,7 @@ mod tests {
     use super::Message;
     use super::PingMessage;
     use super::ProtocolMessage;
+    use super::MAX_MESSAGE_SIZE;
     use crate::protocol::serde::Serialize;
     use bytes::Bytes;
     use std::net::SocketAddr;
@@ -103,4 +126,26 @@ mod tests {
             }))
         );
     }
+
+    #[test]
+    fn it_rejects_oversized_messages() {
+        let b: Bytes = Message::Ping(PingMessage {
+            message: "p".repeat(4096),
+        })
+        .as_bytes()
+        .unwrap();
+
+        assert!(Message::from_bytes(&b).is_err());
+    }
+
+    #[test]
+    fn it_rejects_messages_over_the_frame_limit() {
+        let b = vec![0u8; MAX_MESSAGE_SIZE + 1];
+        assert!(Message::from_bytes(&b).is_err());
+    }
+
+    #[test]
+    fn it_rejects_garbage_bytes() {
+        assert!(Message::from_bytes(&[0xde, 0xad, 0xbe, 0xef]).is_err());
+    }
 }
@@ -0,0 +1,665 @@
+//! Embeddable node
+//!
+//! `BraidpoolNode::builder()` assembles the subsystems the `node` binary runs: the data directory
+//! lock, the bitcoind template pipeline (RPC, ZMQ and the template consumer), outbound
+//! connections to added nodes and the inbound listener. Banned addresses are neither dialed nor
+//! accepted, and bans made while the node runs close open connections to them. Starting the node
+//! returns a `NodeHandle` which stops all of them cooperatively, so the node can run inside other
+//! binaries and tests.
+//!
+//! The template pipeline is supervised: if the ZMQ listener or the template consumer fails or
+//! panics, both are restarted on a fresh channel with exponential backoff, so a bitcoind restart
+//! or a bug in template handling doesn't silently stop templates. The pipeline needs the
+//! `bitcoind` feature, without it the node only relays.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio_util::codec::{FramedRead, FramedWrite};
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::banlist::BanFile;
+use crate::network::Network;
+use crate::{connection, datadir};
+
+#[cfg(feature = "bitcoind")]
+use {
+    crate::{block_template, rpc, zmq},
+    futures::FutureExt,
+    std::fmt::Debug,
+    std::panic::AssertUnwindSafe,
+    std::time::Instant,
+    tokio::sync::mpsc,
+};
+
+/// How long to wait for connections to finish their in-flight work on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// First wait before restarting a supervised task, doubling with each restart
+#[cfg(feature = "bitcoind")]
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+/// Longest wait before restarting a supervised task. A task which ran for longer than this was
+/// healthy, so its backoff starts over.
+#[cfg(feature = "bitcoind")]
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How often the ban file is reloaded and open connections are checked against bans made while
+/// the node runs
+const BAN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The network used when none is given, matching the `--network` default
+pub const DEFAULT_NETWORK: Network = Network::Main;
+
+/// Where to get block templates from
+#[cfg(feature = "bitcoind")]
+#[derive(Debug, Clone)]
+pub struct BitcoinConfig {
+    pub host: String,
+    pub rpc_port: u16,
+    pub rpc_user: Option<String>,
+    pub rpc_pass: Option<String>,
+    pub rpc_cookie: Option<String>,
+    pub zmq_hashblock_port: u16,
+    /// Also select transactions from the mempool and log how they compare to the template
+    pub standalone_templates: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct BraidpoolNodeBuilder {
+    datadir: PathBuf,
+    force: bool,
+    network: Option<Network>,
+    bind: Option<String>,
+    addnodes: Vec<String>,
+    proxy: Option<String>,
+    proxy_fallback: bool,
+    #[cfg(feature = "bitcoind")]
+    bitcoin: Option<BitcoinConfig>,
+}
+
+impl BraidpoolNodeBuilder {
+    /// The data directory, which must exist and is locked for as long as the node runs
+    pub fn datadir(mut self, datadir: impl Into<PathBuf>) -> Self {
+        self.datadir = datadir.into();
+        self
+    }
+
+    /// Start even if the data directory is locked by another node
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// The network to run on. The data directory remembers the first network it was used with
+    /// and refuses any other
+    pub fn network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Accept inbound peers on this address. Without it the node only makes outbound connections
+    pub fn bind(mut self, bind: impl Into<String>) -> Self {
+        self.bind = Some(bind.into());
+        self
+    }
+
+    pub fn addnode(mut self, node: impl Into<String>) -> Self {
+        self.addnodes.push(node.into());
+        self
+    }
+
+    /// Connect to added nodes through a SOCKS5 proxy, optionally falling back to direct
+    /// connections
+    pub fn proxy(mut self, proxy: impl Into<String>, fallback: bool) -> Self {
+        self.proxy = Some(proxy.into());
+        self.proxy_fallback = fallback;
+        self
+    }
+
+    /// Fetch block templates from this bitcoind. Without it the node only relays
+    #[cfg(feature = "bitcoind")]
+    pub fn bitcoin(mut self, bitcoin: BitcoinConfig) -> Self {
+        self.bitcoin = Some(bitcoin);
+        self
+    }
+
+    /// Lock the data directory and connect to bitcoind, without starting anything yet
+    pub fn build(self) -> Result<BraidpoolNode, Box<dyn Error>> {
+        let datadir_lock = datadir::lock(&self.datadir, self.force)?;
+        let network = self.network.unwrap_or(DEFAULT_NETWORK);
+        datadir::check_network(&self.datadir, network)?;
+        let bans = BanFile::open(&self.datadir)?;
+
+        #[cfg(feature = "bitcoind")]
+        let bitcoin = match self.bitcoin {
+            Some(config) => {
+                let rpc = rpc::setup(
+                    config.host.clone(),
+                    config.rpc_port,
+                    config.rpc_user.clone(),
+                    config.rpc_pass.clone(),
+                    config.rpc_cookie.clone(),
+                )?;
+                Some((config, rpc))
+            }
+            None => None,
+        };
+
+        Ok(BraidpoolNode {
+            datadir: self.datadir,
+            datadir_lock,
+            network,
+            bans,
+            bind: self.bind,
+            addnodes: self.addnodes,
+            proxy: self.proxy,
+            proxy_fallback: self.proxy_fallback,
+            #[cfg(feature = "bitcoind")]
+            bitcoin,
+        })
+    }
+}
+
+pub struct BraidpoolNode {
+    datadir: PathBuf,
+    datadir_lock: File,
+    network: Network,
+    bans: BanFile,
+    bind: Option<String>,
+    addnodes: Vec<String>,
+    proxy: Option<String>,
+    proxy_fallback: bool,
+    #[cfg(feature = "bitcoind")]
+    bitcoin: Option<(BitcoinConfig, bitcoincore_rpc::Client)>,
+}
+
+impl BraidpoolNode {
+    pub fn builder() -> BraidpoolNodeBuilder {
+        BraidpoolNodeBuilder::default()
+    }
+
+    pub fn datadir(&self) -> &Path {
+        &self.datadir
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Spawn every subsystem. Outbound connections are made before this returns, and the
+    /// listener is bound so `NodeHandle::local_addr` is known.
+    pub async fn start(self) -> Result<NodeHandle, Box<dyn Error>> {
+        let shutdown = CancellationToken::new();
+        let connections = TaskTracker::new();
+        let bans = Arc::new(Mutex::new(self.bans));
+        let peers = Arc::new(Mutex::new(Peers::default()));
+        let mut tasks = vec![tokio::spawn(close_banned_peers(
+            bans.clone(),
+            peers.clone(),
+        ))];
+
+        #[cfg(feature = "bitcoind")]
+        if let Some((config, rpc)) = self.bitcoin {
+            let zmq_url = format!("tcp://{}:{}", config.host, config.zmq_hashblock_port);
+            let network = self.network;
+            // Shared with the blocking tasks the listener compares standalone templates on
+            let rpc = Arc::new(rpc);
+            tasks.push(tokio::spawn(async move {
+                supervise("Template pipeline", RESTART_BACKOFF, move || {
+                    // A fresh channel each run, as the old one died with whichever end stopped
+                    let (block_template_tx, block_template_rx) = mpsc::channel(1);
+                    let listener = zmq::zmq_hashblock_listener(
+                        zmq_url.clone(),
+                        rpc.clone(),
+                        block_template_tx,
+                        config.standalone_templates,
+                    );
+                    let consumer = block_template::consumer(block_template_rx, network);
+                    async move {
+                        tokio::select! {
+                            result = listener => result,
+                            () = consumer => Err("Block template consumer stopped".into()),
+                        }
+                    }
+                })
+                .await
+            }));
+        }
+
+        for node in &self.addnodes {
+            // Check bans before dialing, so a banned node never hears from us
+            let resolved = match resolve_addnode(node, self.proxy.as_deref()).await {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    log::error!("Error resolving {}: {}", node, e);
+                    continue;
+                }
+            };
+            if resolved.is_empty() {
+                log::warn!("Can't check {} against bans, the proxy resolves it", node);
+            }
+            let banned = {
+                let bans = bans.lock().unwrap();
+                resolved.iter().copied().find(|ip| bans.is_banned(*ip))
+            };
+            if let Some(ip) = banned {
+                log::warn!("Not connecting to banned node {} ({})", node, ip);
+                continue;
+            }
+
+            log::info!("Connecting to node: {}", node);
+            let stream =
+                match connection::dial(node, self.proxy.as_deref(), self.proxy_fallback).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::error!("Error connecting to {}: {}", node, e);
+                        continue;
+                    }
+                };
+            let addr = match stream.peer_addr() {
+                Ok(addr) => addr,
+                Err(e) => {
+                    log::error!("Error connecting to {}: {}", node, e);
+                    continue;
+                }
+            };
+            // When proxied the peer address is the proxy's, so only an address given as the
+            // target is known. Direct connections are checked again in case the name resolved
+            // differently when dialing.
+            let target = match self.proxy {
+                Some(_) => resolved.first().copied(),
+                None => Some(addr.ip()),
+            };
+            if target.is_some_and(|ip| bans.lock().unwrap().is_banned(ip)) {
+                log::warn!("Not connecting to banned node {} ({})", node, addr);
+                continue;
+            }
+            let node = node.clone();
+            spawn_connection(
+                stream,
+                target,
+                &shutdown,
+                &connections,
+                &peers,
+                |mut conn| async move {
+                    if conn.start_from_connect(&addr).await.is_err() {
+                        log::warn!("Peer {} closed connection", node)
+                    }
+                },
+            );
+        }
+
+        let local_addr = match &self.bind {
+            Some(bind) => {
+                log::info!("Binding to {}", bind);
+                let bound = match TcpListener::bind(bind).await {
+                    Ok(listener) => listener.local_addr().map(|addr| (listener, addr)),
+                    Err(e) => Err(e),
+                };
+                // Don't leave the tasks started so far running without a handle to stop them
+                let (listener, local_addr) = match bound {
+                    Ok(bound) => bound,
+                    Err(e) => {
+                        tasks.iter().for_each(JoinHandle::abort);
+                        return Err(e.into());
+                    }
+                };
+                tasks.push(tokio::spawn(accept_loop(
+                    listener,
+                    bans,
+                    peers,
+                    shutdown.clone(),
+                    connections.clone(),
+                )));
+                Some(local_addr)
+            }
+            None => None,
+        };
+
+        Ok(NodeHandle {
+            shutdown,
+            connections,
+            tasks,
+            local_addr,
+            _datadir_lock: self.datadir_lock,
+        })
+    }
+}
+
+/// A running node. Dropping the handle leaves the node running until the runtime shuts down,
+/// use `stop` to shut it down cleanly.
+pub struct NodeHandle {
+    shutdown: CancellationToken,
+    connections: TaskTracker,
+    tasks: Vec<JoinHandle<()>>,
+    local_addr: Option<SocketAddr>,
+    _datadir_lock: File,
+}
+
+impl NodeHandle {
+    /// The address the listener is bound to, if the node accepts inbound peers
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Stop accepting peers, let connections finish their in-flight messages, and stop the
+    /// template pipeline
+    pub async fn stop(self) {
+        log::info!("Shutting down");
+        self.shutdown.cancel();
+        self.connections.close();
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, self.connections.wait())
+            .await
+            .is_err()
+        {
+            log::warn!("Timed out waiting for connections to close");
+        }
+        // The template pipeline holds no state worth flushing
+        for task in self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Run the task `run` creates forever, restarting it with exponential backoff starting at
+/// `backoff` whenever it returns or panics. Aborting the supervisor also stops the task.
+#[cfg(feature = "bitcoind")]
+async fn supervise<F, Fut, E>(name: &str, backoff: Duration, mut run: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Debug,
+{
+    let mut restarts = 0;
+    loop {
+        let started = Instant::now();
+        match AssertUnwindSafe(run()).catch_unwind().await {
+            Ok(Ok(())) => log::warn!("{} stopped", name),
+            Ok(Err(e)) => log::error!("{} stopped: {:?}", name, e),
+            Err(_) => log::error!("{} panicked", name),
+        }
+
+        if started.elapsed() > MAX_RESTART_BACKOFF {
+            restarts = 0;
+        }
+        let wait = backoff
+            .saturating_mul(2u32.saturating_pow(restarts))
+            .min(MAX_RESTART_BACKOFF);
+        restarts += 1;
+        log::info!("Restarting {} in {:?}", name, wait);
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Open connections, with the address each is checked against bans for, so bans made while the
+/// node runs close them too. Connections through a proxy to a hostname have no known address.
+#[derive(Debug, Default)]
+struct Peers {
+    next_id: u64,
+    open: HashMap<u64, (Option<IpAddr>, CancellationToken)>,
+}
+
+impl Peers {
+    fn add(&mut self, addr: Option<IpAddr>, shutdown: CancellationToken) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.open.insert(id, (addr, shutdown));
+        id
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.open.remove(&id);
+    }
+
+    fn close_banned(&mut self, bans: &BanFile) {
+        self.open.retain(|_, (addr, shutdown)| match addr {
+            Some(addr) if bans.is_banned(*addr) => {
+                log::info!("Closing connection to banned address {}", addr);
+                shutdown.cancel();
+                false
+            }
+            _ => true,
+        });
+    }
+}
+
+async fn close_banned_peers(bans: Arc<Mutex<BanFile>>, peers: Arc<Mutex<Peers>>) {
+    let mut interval = tokio::time::interval(BAN_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        // The file is only checked for changes here, checking addresses against the bans
+        // doesn't touch it
+        let mut bans = bans.lock().unwrap();
+        bans.refresh();
+        peers.lock().unwrap().close_banned(&bans);
+    }
+}
+
+/// The addresses added node `node` is checked against for bans before dialing. Hostnames are
+/// resolved here, unless a proxy resolves them so no DNS lookups leak, in which case there are
+/// none to check.
+async fn resolve_addnode(node: &str, proxy: Option<&str>) -> io::Result<Vec<IpAddr>> {
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Ok(vec![addr.ip()]);
+    }
+    if proxy.is_some() {
+        return Ok(Vec::new());
+    }
+    Ok(tokio::net::lookup_host(node)
+        .await?
+        .map(|addr| addr.ip())
+        .collect())
+}
+
+/// Run the connection over `stream` on `connections` until it closes, node shutdown stops it or
+/// `addr` is banned
+fn spawn_connection<F, Fut>(
+    stream: TcpStream,
+    addr: Option<IpAddr>,
+    shutdown: &CancellationToken,
+    connections: &TaskTracker,
+    peers: &Arc<Mutex<Peers>>,
+    run: F,
+) where
+    F: FnOnce(connection::Connection) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let shutdown = shutdown.child_token();
+    let id = peers.lock().unwrap().add(addr, shutdown.clone());
+    let (r, w) = stream.into_split();
+    let framed_reader = FramedRead::new(r, connection::codec());
+    let framed_writer = FramedWrite::new(w, connection::codec());
+    let conn = connection::Connection::new(framed_reader, framed_writer, shutdown);
+    let run = run(conn);
+    let peers = peers.clone();
+    connections.spawn(async move {
+        run.await;
+        peers.lock().unwrap().remove(id);
+    });
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    bans: Arc<Mutex<BanFile>>,
+    peers: Arc<Mutex<Peers>>,
+    shutdown: CancellationToken,
+    connections: TaskTracker,
+) {
+    loop {
+        // Asynchronously wait for an inbound TcpStream, or shutdown.
+        log::info!("Starting accept");
+        let accepted = tokio::select! {
+            _ = shutdown.cancelled() => return,
+            accepted = listener.accept() => accepted,
+        };
+        match accepted {
+            Ok((stream, addr)) if bans.lock().unwrap().is_banned(addr.ip()) => {
+                log::info!("Refusing connection from banned address {}", addr);
+                drop(stream);
+            }
+            Ok((stream, addr)) => {
+                log::info!("Accepted connection from {}", addr);
+                let target = Some(addr.ip());
+                spawn_connection(
+                    stream,
+                    target,
+                    &shutdown,
+                    &connections,
+                    &peers,
+                    |mut conn| async move {
+                        if conn.start_from_accept().await.is_err() {
+                            log::warn!("Peer {} closed connection", addr)
+                        }
+                    },
+                );
+            }
+            Err(e) => log::error!("couldn't get client: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::banlist::BanList;
+    use crate::test_util::test_dir;
+    use std::fs;
+
+    #[tokio::test]
+    async fn it_starts_and_stops_a_relay_node() {
+        let dir = test_dir("service");
+
+        let handle = BraidpoolNode::builder()
+            .datadir(&dir)
+            .bind("127.0.0.1:0")
+            .build()
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+
+        // The data directory stays locked while the node runs
+        assert!(BraidpoolNode::builder().datadir(&dir).build().is_err());
+
+        let addr = handle.local_addr().unwrap();
+        let peer = TcpStream::connect(addr).await.unwrap();
+        handle.stop().await;
+        drop(peer);
+
+        assert!(BraidpoolNode::builder().datadir(&dir).build().is_ok());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "bitcoind")]
+    #[tokio::test]
+    async fn it_restarts_failed_and_panicking_tasks() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::sync::Arc;
+
+        let runs = Arc::new(AtomicU32::new(0));
+        let (done_tx, mut done_rx) = mpsc::channel(1);
+        let counter = runs.clone();
+        let supervisor = tokio::spawn(supervise(
+            "test task",
+            Duration::from_millis(1),
+            move || {
+                let run = counter.fetch_add(1, Ordering::SeqCst);
+                let done_tx = done_tx.clone();
+                async move {
+                    match run {
+                        0 => Err("failed"),
+                        1 => panic!("test task panic"),
+                        _ => {
+                            done_tx.send(()).await.unwrap();
+                            std::future::pending().await
+                        }
+                    }
+                }
+            },
+        ));
+
+        done_rx.recv().await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        supervisor.abort();
+    }
+
+    #[tokio::test]
+    async fn it_refuses_banned_peers() {
+        use crate::protocol::{PingMessage, ProtocolMessage};
+        use futures::{SinkExt, StreamExt};
+        use tokio::io::AsyncReadExt;
+        use tokio_util::codec::Framed;
+
+        let dir = test_dir("service-bans");
+
+        let handle = BraidpoolNode::builder()
+            .datadir(&dir)
+            .bind("127.0.0.1:0")
+            .build()
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+
+        // A ping answered means the connection was accepted before the ban
+        let addr = handle.local_addr().unwrap();
+        let mut connected =
+            Framed::new(TcpStream::connect(addr).await.unwrap(), connection::codec());
+        let ping = PingMessage::start(&addr).unwrap().as_bytes().unwrap();
+        connected.send(ping).await.unwrap();
+        assert!(connected.next().await.unwrap().is_ok());
+
+        // Bans made while the node runs close open connections and apply to the next one
+        let mut bans = BanList::default();
+        bans.ban("127.0.0.0/8".parse().unwrap());
+        bans.save(&dir).unwrap();
+
+        let closed = tokio::time::timeout(BAN_CHECK_INTERVAL * 5, connected.next());
+        assert!(closed.await.unwrap().is_none());
+
+        let mut buf = [0u8; 1];
+
+        let mut peer = TcpStream::connect(handle.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(peer.read(&mut buf).await.unwrap(), 0);
+
+        handle.stop().await;
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_does_not_dial_banned_nodes() {
+        let dir = test_dir("service-addnode");
+        let mut bans = BanList::default();
+        bans.ban("127.0.0.1".parse().unwrap());
+        bans.save(&dir).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let handle = BraidpoolNode::builder()
+            .datadir(&dir)
+            .addnode(listener.local_addr().unwrap().to_string())
+            .build()
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+
+        // Outbound connections are made before `start` returns
+        let accepted = tokio::time::timeout(Duration::from_millis(100), listener.accept());
+        assert!(accepted.await.is_err());
+
+        handle.stop().await;
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+//! Conversions between difficulty, target and work
+//!
+//! Difficulty is measured relative to Bitcoin's difficulty-1 target, `0xffff * 2^208`. This is
+//! the convention Bitcoin Core and Stratum's `mining.set_difficulty` use, so a difficulty shown
+//! to a miner and the target committed in a bead can be derived from each other exactly.
+
+use std::collections::HashMap;
+
+use num::{ToPrimitive, Zero};
+
+use crate::braid::{BeadHash, BeadWork, Target, Work};
+
+/// The target corresponding to a difficulty of 1
+pub fn difficulty_1_target() -> Target {
+    Target::from(0xffffu32) << 208
+}
+
+/// The largest possible 256-bit target
+pub fn max_target() -> Target {
+    (Target::from(1u32) << 256) - 1u32
+}
+
+/// Convert a difficulty to a target, rounding down. Difficulties below 1 give targets above the
+/// difficulty-1 target, capped at the largest 256-bit value. Returns `None` unless `difficulty`
+/// is finite and positive.
+pub fn difficulty_to_target(difficulty: f64) -> Option<Target> {
+    if !difficulty.is_finite() || difficulty <= 0.0 {
+        return None;
+    }
+
+    // Split the difficulty into an exact integer mantissa and a power of two so the division
+    // below happens on integers without losing precision
+    let bits = difficulty.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i64;
+    let fraction = bits & ((1u64 << 52) - 1);
+    let (mantissa, exponent) = if exponent == 0 {
+        (fraction, -1074)
+    } else {
+        (fraction | (1u64 << 52), exponent - 1075)
+    };
+
+    let target = if exponent < 0 {
+        (difficulty_1_target() << (-exponent) as usize) / mantissa
+    } else {
+        difficulty_1_target() / (Target::from(mantissa) << exponent as usize)
+    };
+
+    Some(target.min(max_target()))
+}
+
+/// Convert a target to its difficulty. Returns `None` for a zero target.
+pub fn target_to_difficulty(target: &Target) -> Option<f64> {
+    if target.is_zero() {
+        return None;
+    }
+
+    Some(difficulty_1_target().to_f64()? / target.to_f64()?)
+}
+
+/// Decode a target from the compact `bits` encoding used in block headers. Returns `None` for
+/// negative or overflowing encodings, which Bitcoin treats as invalid.
+pub fn target_from_compact(bits: u32) -> Option<Target> {
+    let exponent = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+
+    let negative = mantissa != 0 && bits & 0x0080_0000 != 0;
+    let overflow = mantissa != 0
+        && (exponent > 34
+            || (mantissa > 0xff && exponent > 33)
+            || (mantissa > 0xffff && exponent > 32));
+    if negative || overflow {
+        return None;
+    }
+
+    let target = if exponent <= 3 {
+        Target::from(mantissa >> (8 * (3 - exponent)))
+    } else {
+        Target::from(mantissa) << (8 * (exponent - 3)) as usize
+    };
+    Some(target)
+}
+
+/// Encode a target in the compact `bits` encoding, dropping all but its top 23 bits like Bitcoin
+/// Core's `GetCompact`. Returns `None` for targets wider than 256 bits.
+pub fn target_to_compact(target: &Target) -> Option<u32> {
+    if target.bits() > 256 {
+        return None;
+    }
+
+    let mut size = target.bits().div_ceil(8) as u32;
+    let mut mantissa = if size <= 3 {
+        target.to_u32()? << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3)) as usize).to_u32()?
+    };
+    // The mantissa is signed, so a set top bit moves into the exponent instead
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    Some(mantissa | size << 24)
+}
+
+/// The difficulty of a compact target, e.g. a block header's `bits`
+pub fn compact_to_difficulty(bits: u32) -> Option<f64> {
+    target_to_difficulty(&target_from_compact(bits)?)
+}
+
+/// The compact target for a difficulty, rounded down to what `bits` can express
+pub fn difficulty_to_compact(difficulty: f64) -> Option<u32> {
+    target_to_compact(&difficulty_to_target(difficulty)?)
+}
+
+/// The expected number of hashes needed to meet a target, `2^256 / (target + 1)`
+pub fn work_from_target(target: &Target) -> Work {
+    (Work::from(1u32) << 256) / (target + 1u32)
+}
+
+/// Compute the work of each bead from its target, for use wherever a `BeadWork` map is
+/// expected (`descendant_work`, `highest_work_path`, `bead_cmp`, ...)
+pub fn bead_work_from_targets(targets: &HashMap<BeadHash, Target>) -> BeadWork {
+    targets
+        .iter()
+        .map(|(bead, target)| (bead.clone(), work_from_target(target)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::bigint::RandBigInt;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn it_maps_difficulty_1_to_the_difficulty_1_target() {
+        assert_eq!(difficulty_to_target(1.0), Some(difficulty_1_target()));
+        assert_eq!(target_to_difficulty(&difficulty_1_target()), Some(1.0));
+    }
+
+    #[test]
+    fn it_scales_the_target_inversely_with_difficulty() {
+        assert_eq!(
+            difficulty_to_target(1024.0),
+            Some(difficulty_1_target() / 1024u32)
+        );
+        assert_eq!(
+            difficulty_to_target(0.5),
+            Some(difficulty_1_target() * 2u32)
+        );
+    }
+
+    #[test]
+    fn it_caps_tiny_difficulties_at_the_max_target() {
+        assert_eq!(difficulty_to_target(1e-30), Some(max_target()));
+    }
+
+    #[test]
+    fn it_round_trips_difficulties() {
+        for difficulty in [0.001, 1.0, 3.5, 65536.0, 1e12, 123456789.123] {
+            let target = difficulty_to_target(difficulty).unwrap();
+            let round_trip = target_to_difficulty(&target).unwrap();
+            assert!(
+                (round_trip - difficulty).abs() / difficulty < 1e-12,
+                "{} became {}",
+                difficulty,
+                round_trip
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_values() {
+        assert_eq!(difficulty_to_target(0.0), None);
+        assert_eq!(difficulty_to_target(-1.0), None);
+        assert_eq!(difficulty_to_target(f64::NAN), None);
+        assert_eq!(difficulty_to_target(f64::INFINITY), None);
+        assert_eq!(target_to_difficulty(&Target::from(0u32)), None);
+    }
+
+    #[test]
+    fn it_decodes_compact_targets() {
+        assert_eq!(target_from_compact(0x1d00ffff), Some(difficulty_1_target()));
+        assert_eq!(
+            target_from_compact(0x207fffff),
+            Some(Target::from(0x7fffffu32) << 232)
+        );
+        assert_eq!(
+            target_from_compact(0x03123456),
+            Some(Target::from(0x123456u32))
+        );
+        assert_eq!(
+            target_from_compact(0x02123456),
+            Some(Target::from(0x1234u32))
+        );
+        assert_eq!(target_from_compact(0), Some(Target::from(0u32)));
+    }
+
+    #[test]
+    fn it_rejects_invalid_compact_targets() {
+        assert_eq!(target_from_compact(0x04923456), None);
+        assert_eq!(target_from_compact(0x23000001), None);
+        assert_eq!(target_from_compact(0x22010000), None);
+    }
+
+    #[test]
+    fn it_encodes_compact_targets() {
+        assert_eq!(target_to_compact(&difficulty_1_target()), Some(0x1d00ffff));
+        assert_eq!(
+            target_to_compact(&Target::from(0x123456u32)),
+            Some(0x03123456)
+        );
+        assert_eq!(
+            target_to_compact(&Target::from(0x1234u32)),
+            Some(0x02123400)
+        );
+        // A set top mantissa bit would make the encoding negative
+        assert_eq!(target_to_compact(&Target::from(0x80u32)), Some(0x02008000));
+        assert_eq!(target_to_compact(&Target::from(0u32)), Some(0));
+        assert_eq!(target_to_compact(&(max_target() + 1u32)), None);
+        assert_eq!(difficulty_to_compact(1.0), Some(0x1d00ffff));
+        assert_eq!(compact_to_difficulty(0x1d00ffff), Some(1.0));
+    }
+
+    #[test]
+    fn it_round_trips_compact_targets() {
+        let mut rng = StdRng::seed_from_u64(208);
+        for _ in 0..10_000 {
+            let target = rng.gen_biguint(256) >> rng.gen_range(0..256usize);
+            let bits = target_to_compact(&target).unwrap();
+            let decoded = target_from_compact(bits).unwrap();
+
+            // Encoding only drops low bits, and what survives encodes the same way again
+            assert!(decoded <= target);
+            assert!(target_to_difficulty(&decoded) >= target_to_difficulty(&target));
+            assert_eq!(target_to_compact(&decoded), Some(bits));
+            if target.bits() <= 23 {
+                assert_eq!(decoded, target);
+            }
+        }
+    }
+
+    #[test]
+    fn it_computes_work_from_targets() {
+        // Every difficulty-1 block adds 0x100010001 to Bitcoin Core's chainwork
+        assert_eq!(
+            work_from_target(&difficulty_1_target()),
+            Work::from(0x1_0001_0001u64)
+        );
+        assert_eq!(work_from_target(&max_target()), Work::from(1u32));
+    }
+}
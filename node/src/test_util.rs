@@ -0,0 +1,13 @@
+//! Helpers shared by the unit tests
+
+use std::fs;
+use std::path::PathBuf;
+
+/// An empty temporary directory for the test `name`, unique to this process so concurrent test
+/// runs don't collide. Tests remove it when they pass.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("braidpool-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
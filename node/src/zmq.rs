@@ -1,12 +1,15 @@
 use crate::block_template;
 use futures::StreamExt;
+use std::error::Error;
+use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 
 pub async fn zmq_hashblock_listener(
     zmq_url: String,
-    rpc: bitcoincore_rpc::Client,
+    rpc: Arc<bitcoincore_rpc::Client>,
     block_template_tx: Sender<bitcoincore_rpc_json::GetBlockTemplateResult>,
-) -> Result<(), bitcoincore_zmq::Error> {
+    standalone_templates: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
     let mut zmq = bitcoincore_zmq::subscribe_async(&[&zmq_url])?;
 
     while let Some(msg) = zmq.next().await {
@@ -18,12 +21,25 @@ pub async fn zmq_hashblock_listener(
                             "Received a new `hashblock` notification via ZeroMQ. \
                             Calling `getblocktemplate` RPC now..."
                         );
-                        block_template::fetcher(&rpc, block_template_tx.clone()).await;
+                        // Nothing to compare against while bitcoind is catching up
+                        let fetched =
+                            block_template::fetcher(&rpc, block_template_tx.clone()).await?;
+                        if let Some(block_template) = fetched.filter(|_| standalone_templates) {
+                            // Reading the mempool and selecting from it blocks, keep it off the
+                            // runtime's workers
+                            let rpc = rpc.clone();
+                            let compared = tokio::task::spawn_blocking(move || {
+                                block_template::compare_standalone_template(&rpc, &block_template)
+                            });
+                            if let Err(e) = compared.await {
+                                log::error!("Standalone template comparison failed: {}", e);
+                            }
+                        }
                     }
                     _ => {}
                 };
             }
-            Err(err) => return Err(err),
+            Err(err) => return Err(err.into()),
         }
     }
 
@@ -2,9 +2,10 @@
 
 cd node
 cargo build
-cargo run -- --bind=localhost:25188 --network=cpunet --rpccookie=~/.bitcoin/cpunet/.cookie --rpcport=28332 &
+# Each node needs its own data directory, as a running node locks it
+cargo run -- --datadir=~/.braidpool/selftest-1 --bind=localhost:25188 --network=cpunet --rpccookie=~/.bitcoin/cpunet/.cookie --rpcport=28332 &
 sleep 1
-cargo run -- --bind=localhost:25189 --network=cpunet --rpccookie=~/.bitcoin/cpunet/.cookie --rpcport=28332 --addnode=localhost:25188 &
+cargo run -- --datadir=~/.braidpool/selftest-2 --bind=localhost:25189 --network=cpunet --rpccookie=~/.bitcoin/cpunet/.cookie --rpcport=28332 --addnode=localhost:25188 &
 sleep 1
 
 echo
@@ -3,8 +3,11 @@ use std::{
     fs,
 };
 
-use node::braid::io_json::{check_cohort, load_braid, save_braid};
+use node::analysis::summarize;
+use node::braid::io_json::{check_cohort, load_braid, random_dag, save_braid, Fixture};
 use node::braid::{self, *};
+use node::target::{bead_work_from_targets, difficulty_1_target, work_from_target};
+use rand::{rngs::StdRng, SeedableRng};
 
 // Cargo runs tests from the node/Cargo.toml file so the cwd is set to node/
 // when this is run, even though the docs say the cwd will be the project root.
@@ -452,6 +455,65 @@ fn test_highest_work_path() {
     );
 }
 
+#[test]
+fn test_highest_work_path_with_targets() {
+    // A diamond where bead 2 was mined against a target 16 times harder than bead 1
+    let parents: Relatives = [
+        (BeadHash::from(0u64), HashSet::new()),
+        (
+            BeadHash::from(1u64),
+            [BeadHash::from(0u64)].iter().cloned().collect(),
+        ),
+        (
+            BeadHash::from(2u64),
+            [BeadHash::from(0u64)].iter().cloned().collect(),
+        ),
+        (
+            BeadHash::from(3u64),
+            [BeadHash::from(1u64), BeadHash::from(2u64)]
+                .iter()
+                .cloned()
+                .collect(),
+        ),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+
+    let targets: HashMap<BeadHash, Target> = [
+        (BeadHash::from(0u64), difficulty_1_target()),
+        (BeadHash::from(1u64), difficulty_1_target()),
+        (BeadHash::from(2u64), difficulty_1_target() >> 4),
+        (BeadHash::from(3u64), difficulty_1_target()),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+    let bead_work = bead_work_from_targets(&targets);
+
+    // With constant work the lower hash (bead 1) wins the tie
+    let constant_work: HashMap<BeadHash, Work> = parents
+        .keys()
+        .map(|b| (b.clone(), Work::from(1u32)))
+        .collect();
+    assert_eq!(
+        braid::highest_work_path(&parents, None, &constant_work),
+        vec![
+            BeadHash::from(0u64),
+            BeadHash::from(1u64),
+            BeadHash::from(3u64)
+        ]
+    );
+    assert_eq!(
+        braid::highest_work_path(&parents, None, &bead_work),
+        vec![
+            BeadHash::from(0u64),
+            BeadHash::from(2u64),
+            BeadHash::from(3u64)
+        ]
+    );
+}
+
 #[test]
 fn test_highest_work_path_files() {
     for entry in fs::read_dir(TEST_CASE_DIR).unwrap() {
@@ -683,3 +745,149 @@ fn test_save_load_braid() {
     // Clean up
     std::fs::remove_file(temp_file).unwrap();
 }
+
+#[test]
+fn test_fixture() {
+    let fixture: Fixture = serde_json::from_str(
+        r#"{
+            "description": "A diamond where the right side has more work",
+            "beads": [
+                {"name": "genesis", "timestamp": 1700000000},
+                {"name": "left", "parents": ["genesis"]},
+                {"name": "right", "parents": ["genesis"], "target": "1000"},
+                {"name": "tip", "parents": ["left", "right"], "timestamp": 1700000030}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let dag = fixture.to_dag().unwrap();
+    let ids = fixture.bead_ids();
+
+    assert_eq!(ids["right"], BeadHash::from(2u64));
+    assert_eq!(
+        dag.parents[&ids["tip"]],
+        [ids["left"].clone(), ids["right"].clone()]
+            .into_iter()
+            .collect()
+    );
+    assert_eq!(
+        dag.bead_work[&ids["right"]],
+        work_from_target(&Target::from(1000u32))
+    );
+    assert_eq!(dag.bead_work[&ids["left"]], Work::from(1u32));
+    assert_eq!(
+        dag.highest_work_path,
+        vec![
+            ids["genesis"].clone(),
+            ids["right"].clone(),
+            ids["tip"].clone()
+        ]
+    );
+    assert_eq!(fixture.timestamps().len(), 2);
+    assert_eq!(fixture.timestamps()[&ids["tip"]], 1700000030);
+}
+
+#[test]
+fn test_fixture_errors() {
+    let unknown_parent: Fixture =
+        serde_json::from_str(r#"{"beads": [{"name": "a", "parents": ["b"]}, {"name": "b"}]}"#)
+            .unwrap();
+    assert!(unknown_parent.to_dag().is_err());
+
+    let duplicate: Fixture =
+        serde_json::from_str(r#"{"beads": [{"name": "a"}, {"name": "a"}]}"#).unwrap();
+    assert!(duplicate.to_dag().is_err());
+}
+
+#[test]
+fn test_random_dag_cohort_profile() {
+    let mut rng = StdRng::seed_from_u64(7);
+    for profile in [
+        vec![1, 1, 1],
+        vec![3, 1, 4, 1, 5],
+        vec![2, 9, 2, 6],
+        vec![8],
+    ] {
+        let dag = random_dag(&mut rng, &profile, None);
+        let widths: Vec<usize> = dag.cohorts.iter().map(|c| c.len()).collect();
+        assert_eq!(widths, profile);
+        for cohort in &dag.cohorts {
+            assert!(check_cohort(cohort, &dag.parents, Some(&dag.children)));
+        }
+    }
+
+    // The same seed gives the same braid
+    let a = random_dag(&mut StdRng::seed_from_u64(1), &[2, 5, 3], None);
+    let b = random_dag(&mut StdRng::seed_from_u64(1), &[2, 5, 3], None);
+    assert_eq!(a.parents, b.parents);
+}
+
+#[test]
+fn test_summarize() {
+    let fixture: Fixture = serde_json::from_str(
+        r#"{
+            "beads": [
+                {"name": "genesis", "timestamp": 100},
+                {"name": "a", "parents": ["genesis"], "timestamp": 110},
+                {"name": "b", "parents": ["genesis"], "timestamp": 105},
+                {"name": "tip", "parents": ["a", "b"], "timestamp": 120}
+            ]
+        }"#,
+    )
+    .unwrap();
+    let summary = summarize(&fixture.to_dag().unwrap(), &fixture.timestamps());
+
+    assert_eq!(summary.beads, 4);
+    let sizes: Vec<usize> = summary.cohorts.iter().map(|c| c.beads).collect();
+    assert_eq!(sizes, vec![1, 2, 1]);
+    assert_eq!(summary.cohort_sizes, [(1, 2), (2, 1)].into_iter().collect());
+    assert_eq!(summary.cohorts[2].cumulative_work, "4");
+    assert_eq!(summary.cohorts[1].timestamp, Some(110));
+    assert_eq!(
+        summary.interval_histogram,
+        [(5, 2), (10, 1)].into_iter().collect()
+    );
+    assert_eq!(summary.cohorts_csv().lines().nth(2), Some("1,2,2,3,110"));
+}
+
+#[test]
+fn test_finalized_up_to() {
+    // Cohorts of one bead each, with work 1, 2, 3, 4, 5
+    let cohorts: Vec<HashSet<BeadHash>> = (0..5u64)
+        .map(|b| [BeadHash::from(b)].into_iter().collect())
+        .collect();
+    let bead_work: BeadWork = (0..5u64)
+        .map(|b| (BeadHash::from(b), Work::from(b + 1)))
+        .collect();
+
+    let finalized =
+        |depth, work: u64| braid::finalized_up_to(&cohorts, &bead_work, depth, &Work::from(work));
+    assert_eq!(finalized(0, 0), Some(4));
+    assert_eq!(finalized(2, 0), Some(2));
+    // The last two cohorts hold 9 work, the last three 12
+    assert_eq!(finalized(2, 9), Some(2));
+    assert_eq!(finalized(2, 10), Some(1));
+    assert_eq!(finalized(5, 0), None);
+    assert_eq!(finalized(1, 15), None);
+}
+
+#[test]
+fn test_finalized_up_to_files() {
+    for entry in fs::read_dir(TEST_CASE_DIR).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let dag = load_braid(&path).unwrap();
+            if dag.cohorts.len() < 2 {
+                continue;
+            }
+            // Requiring only depth, the second to last cohort is final
+            assert_eq!(
+                braid::finalized_up_to(&dag.cohorts, &dag.bead_work, 1, &Work::from(0u64)),
+                Some(dag.cohorts.len() - 2),
+                "Failed on file: {}",
+                path.to_string_lossy()
+            );
+        }
+    }
+}